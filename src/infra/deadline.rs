@@ -0,0 +1,127 @@
+use std::{
+    io::{self, Read},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+// socket timeouts only bound a single read, a client trickling in one byte
+// at a time would never trip them, so every read gets whatever time is left
+// until the deadline instead
+pub struct DeadlineReader<'a> {
+    stream: &'a TcpStream,
+    deadline: Option<Instant>,
+    idle_timeout: Option<Duration>,
+}
+
+impl<'a> DeadlineReader<'a> {
+    pub fn new(stream: &'a TcpStream) -> DeadlineReader<'a> {
+        DeadlineReader {
+            stream,
+            deadline: None,
+            idle_timeout: None,
+        }
+    }
+
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    // no deadline any more, only a limit on how long a single read may wait,
+    // for bodies that may take long but have to keep moving
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.deadline = None;
+        self.idle_timeout = Some(timeout);
+    }
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = match self.deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+                Some(remaining)
+            }
+            None => self.idle_timeout,
+        };
+        if timeout.is_some() {
+            self.stream.set_read_timeout(timeout)?;
+        }
+        let mut stream = self.stream;
+        stream.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod read {
+        use super::super::*;
+        use std::{io::Write, net::TcpListener, time::Duration};
+
+        #[test]
+        fn gives_up_at_the_deadline() {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let (server, _) = listener.accept().unwrap();
+
+            let mut reader = DeadlineReader::new(&server);
+            reader.set_deadline(Some(Instant::now() + Duration::from_millis(100)));
+            client.write_all(b"ab").unwrap();
+
+            let mut buffer = [0; 2];
+            reader.read_exact(&mut buffer).unwrap();
+            assert_eq!(&buffer, b"ab");
+
+            let started = Instant::now();
+            let error = reader.read(&mut buffer).unwrap_err();
+            assert!(matches!(
+                error.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            ));
+            assert!(started.elapsed() < Duration::from_secs(1));
+
+            reader.set_deadline(Some(Instant::now()));
+            assert_eq!(
+                reader.read(&mut buffer).unwrap_err().kind(),
+                io::ErrorKind::TimedOut
+            );
+        }
+    }
+
+    mod set_idle_timeout {
+        use super::super::*;
+        use std::{io::Write, net::TcpListener, thread};
+
+        #[test]
+        fn only_limits_single_reads() {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let (server, _) = listener.accept().unwrap();
+
+            let mut reader = DeadlineReader::new(&server);
+            reader.set_deadline(Some(Instant::now()));
+            reader.set_idle_timeout(Duration::from_millis(150));
+
+            // trickles in for longer than the idle timeout, but never stalls
+            let writer = thread::spawn(move || {
+                for byte in b"abcd" {
+                    thread::sleep(Duration::from_millis(50));
+                    client.write_all(&[*byte]).unwrap();
+                }
+                client
+            });
+            let mut buffer = [0; 4];
+            reader.read_exact(&mut buffer).unwrap();
+            assert_eq!(&buffer, b"abcd");
+            let _client = writer.join().unwrap();
+
+            let error = reader.read(&mut buffer).unwrap_err();
+            assert!(matches!(
+                error.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            ));
+        }
+    }
+}
@@ -1,30 +1,255 @@
-use std::{sync::Mutex, thread};
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
-use rand::Rng;
+type Job = Box<dyn FnOnce() + Send + 'static>;
 
 pub struct ThreadPool {
-    resources: Vec<Mutex<bool>>,
+    workers: Vec<Worker>,
+    sender: Option<mpsc::Sender<Job>>,
+    // jobs handed to the pool that haven't finished yet, running or queued
+    pending: Arc<AtomicUsize>,
+    max_queue: usize,
 }
 
 impl ThreadPool {
-    pub fn new(_size: Option<u32>) -> ThreadPool {
-        let size = _size.unwrap_or(10);
+    pub fn new(size: Option<usize>, max_queue: Option<usize>) -> ThreadPool {
+        let size = size.unwrap_or(10);
+        assert!(size > 0, "thread pool needs at least one worker");
+
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let pending = Arc::new(AtomicUsize::new(0));
+
         ThreadPool {
-            resources: (0..size).map(|_| Mutex::new(true)).collect(),
+            workers: (0..size)
+                .map(|id| Worker::new(id, Arc::clone(&receiver), Arc::clone(&pending)))
+                .collect(),
+            sender: Some(sender),
+            pending,
+            max_queue: max_queue.unwrap_or(64),
         }
     }
 
-    pub fn execute<F>(&self, f: F)
+    // hands `input` back when every worker is busy and the queue is already
+    // `max_queue` jobs deep so the caller can turn the work away itself
+    pub fn execute<T, F>(&self, input: T, f: F) -> Result<(), T>
     where
-        F: FnOnce() + Send + 'static,
+        T: Send + 'static,
+        F: FnOnce(T) + Send + 'static,
     {
-        let round_robin_idx = rand::thread_rng().gen_range(0..=self.resources.len());
-        match self.resources.get(round_robin_idx) {
-            Some(resource) => {
-                let _ = resource.lock();
-                thread::spawn(f);
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => return Err(input),
+        };
+
+        let capacity = self.workers.len() + self.max_queue;
+        let reserved = self
+            .pending
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |pending| {
+                (pending < capacity).then_some(pending + 1)
+            });
+        if reserved.is_err() {
+            return Err(input);
+        }
+
+        // the receivers only go away if every worker died, in which case
+        // there is nobody left to run the job anyway
+        let _ = sender.send(Box::new(move || f(input)));
+        Ok(())
+    }
+
+    // stops taking jobs and waits up to `timeout` for the queue to drain,
+    // workers still busy after that are detached instead of joined
+    pub fn shutdown(mut self, timeout: Duration) -> bool {
+        self.sender.take();
+
+        let deadline = Instant::now() + timeout;
+        while self
+            .workers
+            .iter()
+            .any(|worker| !worker.thread.is_finished())
+        {
+            if Instant::now() >= deadline {
+                self.workers.retain(|worker| worker.thread.is_finished());
+                return false;
             }
-            None => return,
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // closing the channel makes every worker fall out of its loop once
+        // the remaining jobs are done
+        self.sender.take();
+
+        for worker in self.workers.drain(..) {
+            if worker.thread.join().is_err() {
+                eprintln!("worker {} panicked", worker.id);
+            }
+        }
+    }
+}
+
+struct Worker {
+    id: usize,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Worker {
+    fn new(
+        id: usize,
+        receiver: Arc<Mutex<mpsc::Receiver<Job>>>,
+        pending: Arc<AtomicUsize>,
+    ) -> Worker {
+        let thread = thread::Builder::new()
+            .name(format!("worker-{id}"))
+            .spawn(move || loop {
+                let job = match receiver.lock() {
+                    Ok(receiver) => receiver.recv(),
+                    Err(_) => return,
+                };
+                match job {
+                    Ok(job) => {
+                        // a panicking job must not take the worker down with it
+                        if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                            eprintln!("worker {id} recovered from a panicking job");
+                        }
+                        pending.fetch_sub(1, Ordering::AcqRel);
+                    }
+                    Err(_) => return,
+                }
+            })
+            .unwrap();
+
+        Worker { id, thread }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod execute {
+        use super::super::*;
+        use std::{sync::mpsc, time::Duration};
+
+        #[test]
+        fn runs_jobs_on_workers() {
+            let pool = ThreadPool::new(Some(2), None);
+            let (sender, receiver) = mpsc::channel();
+            for i in 0..5 {
+                let sender = sender.clone();
+                assert!(pool.execute(i, move |i| sender.send(i).unwrap()).is_ok());
+            }
+            let mut results: Vec<i32> = (0..5)
+                .map(|_| receiver.recv_timeout(Duration::from_secs(1)).unwrap())
+                .collect();
+            results.sort();
+            assert_eq!(results, vec![0, 1, 2, 3, 4]);
+        }
+
+        #[test]
+        fn rejects_when_queue_is_full() {
+            let pool = ThreadPool::new(Some(1), Some(1));
+            let (started_sender, started) = mpsc::channel();
+            let (release, released) = mpsc::channel::<()>();
+
+            pool.execute((), move |_| {
+                started_sender.send(()).unwrap();
+                released.recv().unwrap();
+            })
+            .unwrap();
+            started.recv_timeout(Duration::from_secs(1)).unwrap();
+
+            assert!(pool.execute((), |_| ()).is_ok());
+            assert_eq!(pool.execute(7, |_| ()), Err(7));
+
+            release.send(()).unwrap();
+        }
+
+        #[test]
+        fn idle_workers_take_jobs_without_queue() {
+            let pool = ThreadPool::new(Some(2), Some(0));
+            let (started_sender, started) = mpsc::channel();
+            let (release, released) = mpsc::channel::<()>();
+            let released = Arc::new(Mutex::new(released));
+
+            for _ in 0..2 {
+                let started_sender = started_sender.clone();
+                let released = Arc::clone(&released);
+                pool.execute((), move |_| {
+                    started_sender.send(()).unwrap();
+                    released.lock().unwrap().recv().unwrap();
+                })
+                .unwrap();
+            }
+            for _ in 0..2 {
+                started.recv_timeout(Duration::from_secs(1)).unwrap();
+            }
+            assert_eq!(pool.execute(7, |_| ()), Err(7));
+
+            release.send(()).unwrap();
+            release.send(()).unwrap();
+        }
+    }
+
+    mod shutdown {
+        use super::super::*;
+        use std::sync::{atomic::AtomicBool, mpsc};
+
+        #[test]
+        fn finishes_queued_jobs() {
+            let pool = ThreadPool::new(Some(1), None);
+            let done = Arc::new(AtomicUsize::new(0));
+            for _ in 0..3 {
+                let done = Arc::clone(&done);
+                pool.execute((), move |_| {
+                    thread::sleep(Duration::from_millis(10));
+                    done.fetch_add(1, Ordering::SeqCst);
+                })
+                .unwrap();
+            }
+            assert!(pool.shutdown(Duration::from_secs(1)));
+            assert_eq!(done.load(Ordering::SeqCst), 3);
+        }
+
+        #[test]
+        fn gives_up_after_timeout() {
+            let pool = ThreadPool::new(Some(1), None);
+            let (release, released) = mpsc::channel::<()>();
+            let finished = Arc::new(AtomicBool::new(false));
+            let job_finished = Arc::clone(&finished);
+            pool.execute((), move |_| {
+                let _ = released.recv();
+                job_finished.store(true, Ordering::SeqCst);
+            })
+            .unwrap();
+
+            assert!(!pool.shutdown(Duration::from_millis(20)));
+            assert!(!finished.load(Ordering::SeqCst));
+            drop(release);
+        }
+    }
+
+    mod worker {
+        use super::super::*;
+        use std::sync::mpsc;
+
+        #[test]
+        fn survives_panicking_job() {
+            let pool = ThreadPool::new(Some(1), None);
+            let (sender, receiver) = mpsc::channel();
+            pool.execute((), |_| panic!("job failed")).unwrap();
+            pool.execute((), move |_| sender.send(()).unwrap()).unwrap();
+            assert!(receiver.recv_timeout(Duration::from_secs(1)).is_ok());
         }
     }
 }
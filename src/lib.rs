@@ -2,40 +2,618 @@ mod infra;
 mod parsers;
 mod routes;
 
-use infra::thread_pool::ThreadPool;
-use parsers::{request, response};
-
-use std::net::{TcpListener, TcpStream};
-
-pub fn run() {
-    let listener = TcpListener::bind("127.0.0.1:8000").unwrap();
-    let pool = ThreadPool::new(None);
-    for stream in listener.incoming() {
-        pool.execute(|| {
-            let stream = match stream {
-                Ok(value) => value,
-                Err(_) => return,
-            };
-            handle_connection(stream);
+use infra::{deadline::DeadlineReader, thread_pool::ThreadPool};
+use parsers::{
+    request::{self, ReadError, RequestLimits},
+    response,
+};
+
+pub use parsers::{
+    headers::{HeaderError, HeaderMap},
+    json::{
+        parse_json, parse_json_with, DuplicateKeys, JsonError, JsonErrorKind, JsonFormat,
+        JsonObject, JsonValue, ParseOptions, RawNumber,
+    },
+    multipart::{MultipartLimits, Part, PartData, SpooledFile},
+    request::HttpRequest,
+    response::{Body, CustomCode, HttpCode, HttpResponse, InvalidHttpCode},
+};
+pub use routes::Router;
+
+use std::{
+    any::Any,
+    io::{self, BufRead, BufReader, ErrorKind},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    pub address: IpAddr,
+    pub port: u16,
+    pub workers: usize,
+    pub max_queue: usize,
+    pub retry_after: Duration,
+    pub drain_timeout: Duration,
+    pub keep_alive_timeout: Duration,
+    pub max_requests_per_connection: usize,
+    pub max_header_bytes: usize,
+    pub max_body_bytes: u64,
+    // how long a client may take to send the request line and headers once
+    // it started, and how long the body may stall between reads
+    pub read_timeout: Duration,
+    // how long a single write may stall, a response that keeps moving can
+    // take as long as it needs
+    pub write_timeout: Duration,
+    pub multipart: MultipartLimits,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 8000,
+            workers: 10,
+            max_queue: 64,
+            retry_after: Duration::from_secs(1),
+            drain_timeout: Duration::from_secs(30),
+            keep_alive_timeout: Duration::from_secs(5),
+            max_requests_per_connection: 100,
+            max_header_bytes: 16 * 1024,
+            max_body_bytes: 1024 * 1024,
+            read_timeout: Duration::from_secs(10),
+            write_timeout: Duration::from_secs(30),
+            multipart: MultipartLimits::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn address(mut self, address: IpAddr) -> ServerConfig {
+        self.address = address;
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> ServerConfig {
+        self.port = port;
+        self
+    }
+
+    pub fn workers(mut self, workers: usize) -> ServerConfig {
+        self.workers = workers;
+        self
+    }
+
+    pub fn max_queue(mut self, max_queue: usize) -> ServerConfig {
+        self.max_queue = max_queue;
+        self
+    }
+
+    pub fn retry_after(mut self, retry_after: Duration) -> ServerConfig {
+        self.retry_after = retry_after;
+        self
+    }
+
+    pub fn drain_timeout(mut self, drain_timeout: Duration) -> ServerConfig {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    pub fn keep_alive_timeout(mut self, keep_alive_timeout: Duration) -> ServerConfig {
+        self.keep_alive_timeout = keep_alive_timeout;
+        self
+    }
+
+    pub fn max_requests_per_connection(mut self, max_requests: usize) -> ServerConfig {
+        self.max_requests_per_connection = max_requests;
+        self
+    }
+
+    pub fn max_header_bytes(mut self, max_header_bytes: usize) -> ServerConfig {
+        self.max_header_bytes = max_header_bytes;
+        self
+    }
+
+    pub fn max_body_bytes(mut self, max_body_bytes: u64) -> ServerConfig {
+        self.max_body_bytes = max_body_bytes;
+        self
+    }
+
+    pub fn read_timeout(mut self, read_timeout: Duration) -> ServerConfig {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    pub fn write_timeout(mut self, write_timeout: Duration) -> ServerConfig {
+        self.write_timeout = write_timeout;
+        self
+    }
+
+    pub fn multipart(mut self, multipart: MultipartLimits) -> ServerConfig {
+        self.multipart = multipart;
+        self
+    }
+
+    pub fn build(self, router: Router) -> io::Result<Server> {
+        if self.workers == 0 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "server needs at least one worker",
+            ));
+        }
+        if self.max_requests_per_connection == 0 || self.keep_alive_timeout.is_zero() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "connections need to serve at least one request",
+            ));
+        }
+        if self.max_header_bytes == 0 || self.read_timeout.is_zero() || self.write_timeout.is_zero()
+        {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "requests need room for headers and time to arrive",
+            ));
+        }
+
+        let listener = TcpListener::bind(SocketAddr::new(self.address, self.port))?;
+        Ok(Server {
+            listener,
+            shared: Arc::new(Shared {
+                shutting_down: AtomicBool::new(false),
+                router,
+                limits: RequestLimits {
+                    max_header_bytes: self.max_header_bytes,
+                    max_body_bytes: self.max_body_bytes,
+                    multipart: self.multipart.clone(),
+                },
+                config: self,
+            }),
         })
     }
 }
 
-fn handle_connection(stream: TcpStream) {
-    let (headers, body) = match request::split_request(&stream) {
-        Some(value) => value,
-        None => {
-            return response::send_response(
-                stream,
-                response::http_error(response::HttpCode::BadRequest, "could not parse headers"),
-            )
+pub struct Server {
+    listener: TcpListener,
+    shared: Arc<Shared>,
+}
+
+// everything a worker needs to serve a connection
+struct Shared {
+    shutting_down: AtomicBool,
+    router: Router,
+    limits: RequestLimits,
+    config: ServerConfig,
+}
+
+impl Server {
+    pub fn builder() -> ServerConfig {
+        ServerConfig::default()
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn run(&self) {
+        let config = &self.shared.config;
+        let pool = ThreadPool::new(Some(config.workers), Some(config.max_queue));
+        let retry_after = config.retry_after.as_secs().max(1) as u32;
+
+        while !self.shared.shutting_down.load(Ordering::Acquire) {
+            let stream = match self.listener.accept() {
+                // most likely the connection `shutdown` made to wake us up
+                Ok(_) if self.shared.shutting_down.load(Ordering::Acquire) => break,
+                Ok((stream, _)) => stream,
+                // errors like running out of file descriptors don't go away
+                // by retrying straight away
+                Err(_) => {
+                    thread::sleep(ACCEPT_ERROR_BACKOFF);
+                    continue;
+                }
+            };
+            // a client that stops reading can't stall writes forever
+            if stream
+                .set_write_timeout(Some(config.write_timeout))
+                .is_err()
+            {
+                continue;
+            }
+            let shared = Arc::clone(&self.shared);
+            if let Err(stream) =
+                pool.execute(stream, move |stream| handle_connection(stream, &shared))
+            {
+                response::send_response(stream, response::service_unavailable(retry_after));
+            }
+        }
+
+        if !pool.shutdown(config.drain_timeout) {
+            eprintln!(
+                "connections still in flight after {:?}, shutting down anyway",
+                config.drain_timeout
+            );
+        }
+    }
+
+    pub fn shutdown(&self) {
+        self.shared.shutting_down.store(true, Ordering::Release);
+
+        // accept() blocks until somebody connects, so somebody does
+        let mut address = match self.listener.local_addr() {
+            Ok(address) => address,
+            Err(_) => return,
+        };
+        if address.ip().is_unspecified() {
+            address.set_ip(match address {
+                SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+        }
+        let _ = TcpStream::connect_timeout(&address, Duration::from_secs(1));
+    }
+}
+
+fn handle_connection(stream: TcpStream, shared: &Shared) {
+    let config = &shared.config;
+    let mut reader = BufReader::new(DeadlineReader::new(&stream));
+    let mut writer = &stream;
+
+    for served in 1.. {
+        // also bounds how long an idle keep-alive connection can hold a worker
+        reader
+            .get_mut()
+            .set_deadline(Some(Instant::now() + config.keep_alive_timeout));
+        match reader.fill_buf() {
+            Ok(buffer) if !buffer.is_empty() => (),
+            _ => return,
         }
-    };
+        // from the first byte on the request line and headers have to arrive
+        // in time, the body may take longer as long as it keeps coming
+        reader
+            .get_mut()
+            .set_deadline(Some(Instant::now() + config.read_timeout));
+        let read = request::read_head(&mut reader, &shared.limits).and_then(|head| {
+            reader.get_mut().set_idle_timeout(config.read_timeout);
+            request::read_body(&mut reader, head, &shared.limits)
+        });
+
+        let (headers, body) = match read {
+            Ok(value) => value,
+            Err(error) => {
+                let response = match error {
+                    ReadError::Closed => return,
+                    ReadError::Malformed => {
+                        response::http_error(HttpCode::BadRequest, "could not parse headers")
+                    }
+                    ReadError::Unsupported => response::http_error(
+                        HttpCode::NotImplemented,
+                        "only the chunked transfer coding is supported",
+                    ),
+                    ReadError::HeadersTooLarge => response::http_error(
+                        HttpCode::RequestHeaderFieldsTooLarge,
+                        "request header fields are too large",
+                    ),
+                    ReadError::TimedOut => {
+                        response::http_error(HttpCode::RequestTimeout, "request took too long")
+                    }
+                    ReadError::TooLarge => {
+                        response::http_error(HttpCode::ContentTooLarge, "request body is too large")
+                    }
+                    ReadError::Storage(error) => {
+                        eprintln!("could not spool request body: {error}");
+                        response::http_error(
+                            HttpCode::InternalServerError,
+                            "could not store request body",
+                        )
+                    }
+                };
+                return send_final_response(&mut writer, response, false, false);
+            }
+        };
 
-    let request = match request::parse_request(headers, body) {
-        Ok(request) => request,
-        Err(response) => return response::send_response(stream, response),
-    };
+        let request = match request::parse_request(headers, body) {
+            Ok(request) => request,
+            Err(response) => return send_final_response(&mut writer, response, false, false),
+        };
 
-    routes::handle_request(request, stream);
+        let keep_alive = request.keep_alive()
+            && served < config.max_requests_per_connection
+            && !shared.shutting_down.load(Ordering::Acquire);
+        let http_1_0 = request.version == "HTTP/1.0";
+        let head_request = request.method == "HEAD";
+
+        let mut response = handle_request(request, &shared.router);
+        // without chunked coding the end of the body is marked by closing
+        let keep_alive = keep_alive
+            && !(http_1_0 && response.body.length().is_none())
+            && !response.header("Connection").is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|token| token.trim().eq_ignore_ascii_case("close"))
+            });
+        if !keep_alive {
+            return send_final_response(&mut writer, response, head_request, !http_1_0);
+        }
+        if http_1_0 {
+            response.headers.append("Connection", "keep-alive");
+        }
+        if response::write_response(&mut writer, response, head_request, !http_1_0).is_err() {
+            return;
+        }
+    }
+}
+
+fn handle_request(request: HttpRequest, router: &Router) -> HttpResponse {
+    let (method, uri) = (request.method.clone(), request.uri.clone());
+    match panic::catch_unwind(AssertUnwindSafe(|| router.handle(request))) {
+        Ok(response) => response,
+        Err(cause) => {
+            eprintln!(
+                "handler for {method} {uri} panicked: {}",
+                panic_message(&cause)
+            );
+            response::http_error(HttpCode::InternalServerError, "internal server error")
+        }
+    }
+}
+
+fn send_final_response(
+    writer: &mut &TcpStream,
+    mut response: HttpResponse,
+    head_request: bool,
+    chunked_allowed: bool,
+) {
+    if response.header("Connection").is_none() {
+        response.headers.append("Connection", "close");
+    }
+    // the client may already have hung up, there is nobody left to tell
+    let _ = response::write_response(writer, response, head_request, chunked_allowed);
+}
+
+fn panic_message(cause: &Box<dyn Any + Send>) -> &str {
+    match cause.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => match cause.downcast_ref::<String>() {
+            Some(message) => message,
+            None => "unknown cause",
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod shutdown {
+        use super::super::*;
+
+        #[test]
+        fn wakes_up_blocked_accept() {
+            let server = Arc::new(Server::builder().port(0).build(Router::new()).unwrap());
+            let running = Arc::clone(&server);
+            let run = thread::spawn(move || running.run());
+
+            thread::sleep(Duration::from_millis(50));
+            let started = Instant::now();
+            server.shutdown();
+            run.join().unwrap();
+            assert!(started.elapsed() < Duration::from_secs(1));
+        }
+
+        #[test]
+        fn wakes_up_unspecified_address() {
+            let server = Server::builder()
+                .address(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+                .port(0)
+                .build(Router::new())
+                .unwrap();
+            let server = Arc::new(server);
+            let running = Arc::clone(&server);
+            let run = thread::spawn(move || running.run());
+
+            server.shutdown();
+            run.join().unwrap();
+        }
+    }
+
+    mod handle_request {
+        use super::super::*;
+        use parsers::request::RequestBody;
+
+        fn request(method: &str, uri: &str) -> HttpRequest {
+            let lines = vec![format!("{method} {uri} HTTP/1.1")];
+            request::parse_request(lines, RequestBody::Bytes(Vec::new()))
+                .ok()
+                .unwrap()
+        }
+
+        #[test]
+        fn passes_responses_through() {
+            let mut router = Router::new();
+            router.get("/", |_| HttpResponse::text(HttpCode::Created, "made"));
+            assert_eq!(
+                handle_request(request("GET", "/"), &router).code.value(),
+                201
+            );
+        }
+
+        #[test]
+        fn turns_panics_into_500() {
+            let mut router = Router::new();
+            router
+                .get("/str", |_| panic!("handler failed"))
+                .get("/string", |request| panic!("no {}", request.path));
+
+            for uri in ["/str", "/string?page=2"] {
+                let response = handle_request(request("GET", uri), &router);
+                assert_eq!(response.code, HttpCode::InternalServerError);
+                match response.body {
+                    Body::Full(bytes) => assert_eq!(bytes, b"\"internal server error\""),
+                    _ => panic!("expected a full body"),
+                }
+            }
+        }
+    }
+
+    mod handle_connection {
+        use super::super::*;
+        use std::io::{Read, Write};
+
+        fn connect(config: ServerConfig) -> (TcpStream, thread::JoinHandle<()>) {
+            let mut router = Router::new();
+            router
+                .get("/", |_| HttpResponse::text(HttpCode::Ok, "hi"))
+                .get("/stream", |_| {
+                    HttpResponse::text(HttpCode::Ok, Body::chunks(["a", "b"]))
+                })
+                .post("/echo", |request| {
+                    let text = request.text().unwrap_or_default().to_string();
+                    HttpResponse::text(HttpCode::Ok, text)
+                });
+            let server = config.port(0).build(router).unwrap();
+
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            client
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            let shared = Arc::clone(&server.shared);
+            let worker = thread::spawn(move || handle_connection(stream, &shared));
+            (client, worker)
+        }
+
+        // everything the server sends until it closes the connection
+        fn exchange(client: &mut TcpStream, requests: &str) -> String {
+            client.write_all(requests.as_bytes()).unwrap();
+            let mut output = String::new();
+            client.read_to_string(&mut output).unwrap();
+            output
+        }
+
+        #[test]
+        fn serves_several_requests_on_one_socket() {
+            let (mut client, worker) = connect(ServerConfig::default());
+            let output = exchange(
+                &mut client,
+                "GET / HTTP/1.1\r\n\r\n\
+                 HEAD / HTTP/1.1\r\n\r\n\
+                 GET / HTTP/1.1\r\nConnection: close\r\n\r\n",
+            );
+            worker.join().unwrap();
+
+            assert_eq!(output.matches("HTTP/1.1 200 OK\r\n").count(), 3);
+            assert_eq!(output.matches("\r\n\r\nhi").count(), 2);
+            assert_eq!(output.matches("Connection: close\r\n").count(), 1);
+            assert!(output.ends_with("\r\n\r\nhi"));
+        }
+
+        #[test]
+        fn closes_after_max_requests() {
+            let config = ServerConfig::default().max_requests_per_connection(2);
+            let (mut client, worker) = connect(config);
+            let output = exchange(&mut client, "GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+            worker.join().unwrap();
+
+            let (first, second) = output.split_once("hi").unwrap();
+            assert!(!first.contains("Connection:"));
+            assert!(second.contains("Connection: close\r\n"));
+            assert!(second.ends_with("\r\n\r\nhi"));
+        }
+
+        #[test]
+        fn closes_http_1_0_body_of_unknown_length() {
+            let (mut client, worker) = connect(ServerConfig::default());
+            let output = exchange(
+                &mut client,
+                "GET /stream HTTP/1.0\r\nConnection: keep-alive\r\n\r\n",
+            );
+            worker.join().unwrap();
+
+            assert!(output.contains("Connection: close\r\n"));
+            assert!(!output.contains("Transfer-Encoding"));
+            assert!(output.ends_with("\r\n\r\nab"));
+        }
+
+        #[test]
+        fn slow_body_only_has_to_keep_moving() {
+            let config = ServerConfig::default().read_timeout(Duration::from_millis(150));
+            let (mut client, worker) = connect(config);
+            client
+                .write_all(b"POST /echo HTTP/1.1\r\nContent-Length: 4\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            for byte in b"body" {
+                thread::sleep(Duration::from_millis(60));
+                client.write_all(&[*byte]).unwrap();
+            }
+            let output = exchange(&mut client, "");
+            worker.join().unwrap();
+
+            assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(output.ends_with("\r\n\r\nbody"));
+        }
+
+        #[test]
+        fn slow_headers_time_out() {
+            let config = ServerConfig::default().read_timeout(Duration::from_millis(150));
+            let (mut client, worker) = connect(config);
+            for line in [
+                "GET / HTTP/1.1\r\n",
+                "Host: x\r\n",
+                "Accept: */*\r\n",
+                "\r\n",
+            ] {
+                client.write_all(line.as_bytes()).unwrap();
+                thread::sleep(Duration::from_millis(60));
+            }
+            let output = exchange(&mut client, "");
+            worker.join().unwrap();
+
+            assert!(output.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        }
+
+        #[test]
+        fn closes_idle_connections() {
+            let config = ServerConfig::default().keep_alive_timeout(Duration::from_millis(100));
+            let (mut client, worker) = connect(config);
+            let started = Instant::now();
+            let output = exchange(&mut client, "GET / HTTP/1.1\r\n\r\n");
+            worker.join().unwrap();
+
+            assert!(output.ends_with("\r\n\r\nhi"));
+            assert!(!output.contains("Connection: close"));
+            assert!(started.elapsed() >= Duration::from_millis(100));
+            assert!(started.elapsed() < Duration::from_secs(2));
+        }
+    }
+
+    mod panic_message {
+        use super::super::*;
+
+        fn cause(f: impl FnOnce()) -> Box<dyn Any + Send> {
+            panic::catch_unwind(AssertUnwindSafe(f)).unwrap_err()
+        }
+
+        #[test]
+        fn reads_string_payloads() {
+            assert_eq!(panic_message(&cause(|| panic!("static"))), "static");
+            assert_eq!(
+                panic_message(&cause(|| panic!("formatted {}", 1))),
+                "formatted 1"
+            );
+        }
+
+        #[test]
+        fn falls_back_for_other_payloads() {
+            assert_eq!(
+                panic_message(&cause(|| panic::panic_any(7))),
+                "unknown cause"
+            );
+        }
+    }
 }
@@ -1,5 +1,188 @@
-use rust_web::run;
+use std::{env, fs, process, sync::Arc, time::Duration};
+
+use rust_web::{
+    HttpCode, HttpRequest, HttpResponse, JsonObject, JsonValue, Router, Server, ServerConfig,
+};
+
+const USAGE: &str = "usage: rust_web [options]
+
+options:
+  --address <ip>            address to bind, IPv4 or IPv6  [RUST_WEB_ADDRESS]
+  --port <port>             port to listen on              [RUST_WEB_PORT]
+  --workers <count>         number of pool workers         [RUST_WEB_WORKERS]
+  --max-queue <count>       connections allowed to wait    [RUST_WEB_MAX_QUEUE]
+  --retry-after <seconds>   Retry-After sent when shedding [RUST_WEB_RETRY_AFTER]
+  --drain-timeout <seconds> grace period on shutdown       [RUST_WEB_DRAIN_TIMEOUT]
+  --keep-alive <seconds>    idle time before closing       [RUST_WEB_KEEP_ALIVE]
+  --max-requests <count>    requests per connection        [RUST_WEB_MAX_REQUESTS]
+  --max-header-bytes <size> request line and headers       [RUST_WEB_MAX_HEADER_BYTES]
+  --max-body-bytes <size>   buffered request body          [RUST_WEB_MAX_BODY_BYTES]
+  --read-timeout <seconds>  time to receive the headers    [RUST_WEB_READ_TIMEOUT]
+  --write-timeout <seconds> longest stall while writing   [RUST_WEB_WRITE_TIMEOUT]
+  -h, --help                print this message";
+
+// every option can come from the environment, flags on the command line win
+const OPTIONS: [(&str, &str); 12] = [
+    ("--address", "RUST_WEB_ADDRESS"),
+    ("--port", "RUST_WEB_PORT"),
+    ("--workers", "RUST_WEB_WORKERS"),
+    ("--max-queue", "RUST_WEB_MAX_QUEUE"),
+    ("--retry-after", "RUST_WEB_RETRY_AFTER"),
+    ("--drain-timeout", "RUST_WEB_DRAIN_TIMEOUT"),
+    ("--keep-alive", "RUST_WEB_KEEP_ALIVE"),
+    ("--max-requests", "RUST_WEB_MAX_REQUESTS"),
+    ("--max-header-bytes", "RUST_WEB_MAX_HEADER_BYTES"),
+    ("--max-body-bytes", "RUST_WEB_MAX_BODY_BYTES"),
+    ("--read-timeout", "RUST_WEB_READ_TIMEOUT"),
+    ("--write-timeout", "RUST_WEB_WRITE_TIMEOUT"),
+];
 
 fn main() {
-    run();
+    let config = match read_config(env::args().skip(1).collect()) {
+        Ok(config) => config,
+        Err(message) => {
+            eprintln!("{message}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+
+    let mut router = Router::new();
+    router
+        .get("/", hello_world)
+        .post("/", test_post)
+        .post("/greet", greet)
+        .post("/upload", upload)
+        .not_found(not_found);
+
+    let server = match config.build(router) {
+        Ok(server) => Arc::new(server),
+        Err(error) => {
+            eprintln!("could not start server: {error}");
+            process::exit(1);
+        }
+    };
+
+    let handle = Arc::clone(&server);
+    ctrlc::set_handler(move || handle.shutdown()).unwrap();
+
+    server.run();
+}
+
+fn read_config(args: Vec<String>) -> Result<ServerConfig, String> {
+    let mut settings: Vec<(&str, String)> = OPTIONS
+        .iter()
+        .filter_map(|(flag, variable)| env::var(variable).ok().map(|value| (*flag, value)))
+        .collect();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            println!("{USAGE}");
+            process::exit(0);
+        }
+
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), value.to_string()),
+            None => match args.next() {
+                Some(value) => (arg, value),
+                None => return Err(format!("missing value for {arg}")),
+            },
+        };
+        let flag = match OPTIONS.iter().find(|(option, _)| *option == flag) {
+            Some((option, _)) => *option,
+            None => return Err(format!("unknown option {flag}")),
+        };
+        settings.push((flag, value));
+    }
+
+    let mut config = Server::builder();
+    for (flag, value) in settings {
+        let invalid = || format!("invalid value for {flag}: {value}");
+        config = match flag {
+            "--address" => config.address(value.parse().map_err(|_| invalid())?),
+            "--port" => config.port(value.parse().map_err(|_| invalid())?),
+            "--workers" => config.workers(value.parse().map_err(|_| invalid())?),
+            "--max-queue" => config.max_queue(value.parse().map_err(|_| invalid())?),
+            "--retry-after" => {
+                config.retry_after(Duration::from_secs(value.parse().map_err(|_| invalid())?))
+            }
+            "--drain-timeout" => {
+                config.drain_timeout(Duration::from_secs(value.parse().map_err(|_| invalid())?))
+            }
+            "--keep-alive" => config
+                .keep_alive_timeout(Duration::from_secs(value.parse().map_err(|_| invalid())?)),
+            "--max-requests" => {
+                config.max_requests_per_connection(value.parse().map_err(|_| invalid())?)
+            }
+            "--max-header-bytes" => config.max_header_bytes(value.parse().map_err(|_| invalid())?),
+            "--max-body-bytes" => config.max_body_bytes(value.parse().map_err(|_| invalid())?),
+            "--read-timeout" => {
+                config.read_timeout(Duration::from_secs(value.parse().map_err(|_| invalid())?))
+            }
+            "--write-timeout" => {
+                config.write_timeout(Duration::from_secs(value.parse().map_err(|_| invalid())?))
+            }
+            _ => config,
+        };
+    }
+
+    Ok(config)
+}
+
+fn hello_world(_: HttpRequest) -> HttpResponse {
+    HttpResponse::html(
+        HttpCode::Ok,
+        fs::read_to_string("./src/hello.html").unwrap(),
+    )
+}
+
+fn test_post(request: HttpRequest) -> HttpResponse {
+    if request.bytes().is_empty() {
+        return HttpResponse::json(HttpCode::Ok, JsonValue::Array(vec![]));
+    }
+    match request.json() {
+        Ok(value) => HttpResponse::json(HttpCode::Ok, value.clone()),
+        Err(response) => response,
+    }
+}
+
+fn greet(request: HttpRequest) -> HttpResponse {
+    let name = match request.form("name") {
+        Some(name) if !name.trim().is_empty() => name,
+        _ => "stranger",
+    };
+    let name = name
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+    HttpResponse::html(
+        HttpCode::Ok,
+        format!("<!DOCTYPE html>\n<h1>Hello, {name}!</h1>\n"),
+    )
+}
+
+// echoes back what was received, the files themselves are discarded
+fn upload(request: HttpRequest) -> HttpResponse {
+    let files = request
+        .files()
+        .iter()
+        .map(|part| {
+            JsonValue::Object(JsonObject::from([
+                (
+                    "name".to_string(),
+                    JsonValue::String(part.filename.clone().unwrap_or_default()),
+                ),
+                ("size".to_string(), JsonValue::UInt(part.size())),
+            ]))
+        })
+        .collect();
+    HttpResponse::json(HttpCode::Ok, JsonValue::Array(files))
+}
+
+fn not_found(_: HttpRequest) -> HttpResponse {
+    HttpResponse::html(
+        HttpCode::NotFound,
+        fs::read_to_string("./src/404.html").unwrap(),
+    )
 }
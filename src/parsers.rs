@@ -1,3 +1,6 @@
+pub mod headers;
 pub mod json;
+pub mod multipart;
 pub mod request;
 pub mod response;
+pub mod urlencoded;
@@ -0,0 +1,267 @@
+use std::{fmt, slice};
+
+// header fields in the order they were received or added, names keep their
+// original casing for output but are matched case-insensitively
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> HeaderMap {
+        HeaderMap::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    // repeated fields like Set-Cookie or Accept, in order
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.iter()
+            .filter(move |(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    // a CR or LF copied in from user input would let it write headers or
+    // a whole response of its own, values that came from the client should
+    // go through here
+    pub fn try_append(&mut self, name: &str, value: &str) -> Result<(), HeaderError> {
+        check_field(name, value)?;
+        self.entries.push((name.to_string(), value.to_string()));
+        Ok(())
+    }
+
+    // replaces every existing field of that name
+    pub fn try_insert(&mut self, name: &str, value: &str) -> Result<(), HeaderError> {
+        check_field(name, value)?;
+        self.remove(name);
+        self.entries.push((name.to_string(), value.to_string()));
+        Ok(())
+    }
+
+    // for fields the caller knows are valid, panics otherwise
+    pub fn append(&mut self, name: &str, value: &str) {
+        if let Err(error) = self.try_append(name, value) {
+            panic!("{error}: {name:?}");
+        }
+    }
+
+    pub fn insert(&mut self, name: &str, value: &str) {
+        if let Err(error) = self.try_insert(name, value) {
+            panic!("{error}: {name:?}");
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries
+            .retain(|(header, _)| !header.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(header, value)| (header.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<'a> IntoIterator for &'a HeaderMap {
+    type Item = &'a (String, String);
+    type IntoIter = slice::Iter<'a, (String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+impl FromIterator<(String, String)> for HeaderMap {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in iter {
+            headers.append(&name, &value);
+        }
+        headers
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeaderError {
+    InvalidName,
+    InvalidValue,
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidName => write!(f, "invalid header name"),
+            Self::InvalidValue => write!(f, "invalid header value"),
+        }
+    }
+}
+
+fn check_field(name: &str, value: &str) -> Result<(), HeaderError> {
+    if !is_valid_name(name) {
+        return Err(HeaderError::InvalidName);
+    }
+    if !is_valid_value(value) {
+        return Err(HeaderError::InvalidValue);
+    }
+    Ok(())
+}
+
+// `name: value` split on the first colon only, values may contain more
+// of them (`Host: localhost:8000`). the name has to be a token right up to
+// the colon, whitespace before it or a folded continuation line is how one
+// hop ends up seeing a field another one doesn't (RFC 9112 section 5)
+pub fn split_header(line: &str) -> Option<(&str, &str)> {
+    let (name, value) = line.split_once(':')?;
+    let value = value.trim_matches([' ', '\t']);
+    (is_valid_name(name) && is_valid_value(value)).then_some((name, value))
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+// anything but control characters, tabs are fine (RFC 9110 section 5.5)
+fn is_valid_value(value: &str) -> bool {
+    value
+        .bytes()
+        .all(|byte| byte == b'\t' || !byte.is_ascii_control())
+}
+
+#[cfg(test)]
+mod tests {
+    mod header_map {
+        use super::super::*;
+
+        fn headers(fields: &[(&str, &str)]) -> HeaderMap {
+            fields
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        }
+
+        #[test]
+        fn case_insensitive_lookup() {
+            let headers = headers(&[("Content-Type", "text/plain")]);
+            assert_eq!(headers.get("content-type"), Some("text/plain"));
+            assert_eq!(headers.get("CONTENT-TYPE"), Some("text/plain"));
+            assert!(headers.contains("Content-type"));
+            assert_eq!(headers.get("Accept"), None);
+        }
+
+        #[test]
+        fn keeps_repeated_fields_in_order() {
+            let mut headers = headers(&[("Set-Cookie", "a=1"), ("Vary", "Accept")]);
+            headers.append("set-cookie", "b=2");
+            assert_eq!(
+                headers.get_all("SET-COOKIE").collect::<Vec<_>>(),
+                ["a=1", "b=2"]
+            );
+            assert_eq!(
+                headers.iter().collect::<Vec<_>>(),
+                [
+                    ("Set-Cookie", "a=1"),
+                    ("Vary", "Accept"),
+                    ("set-cookie", "b=2")
+                ]
+            );
+        }
+
+        #[test]
+        fn insert_replaces_all() {
+            let mut headers = headers(&[("Accept", "a"), ("accept", "b"), ("Host", "x")]);
+            headers.insert("ACCEPT", "c");
+            assert_eq!(
+                headers.iter().collect::<Vec<_>>(),
+                [("Host", "x"), ("ACCEPT", "c")]
+            );
+
+            headers.remove("host");
+            assert_eq!(headers.len(), 1);
+        }
+
+        #[test]
+        #[should_panic(expected = "invalid header value: \"Location\"")]
+        fn refuses_line_breaks_in_values() {
+            HeaderMap::new().append("Location", "/\r\nSet-Cookie: session=stolen");
+        }
+
+        #[test]
+        #[should_panic(expected = "invalid header name")]
+        fn refuses_invalid_names() {
+            HeaderMap::new().insert("X-Bad\r\nName", "value");
+        }
+
+        #[test]
+        fn try_variants_report_invalid_fields() {
+            let mut headers = HeaderMap::new();
+            headers.append("Location", "/home");
+            assert_eq!(
+                headers.try_insert("Location", "/\r\nSet-Cookie: a=b"),
+                Err(HeaderError::InvalidValue)
+            );
+            assert_eq!(
+                headers.try_append("Bad Name", "value"),
+                Err(HeaderError::InvalidName)
+            );
+            assert_eq!(headers.iter().collect::<Vec<_>>(), [("Location", "/home")]);
+
+            assert_eq!(headers.try_insert("location", "/next"), Ok(()));
+            assert_eq!(headers.iter().collect::<Vec<_>>(), [("location", "/next")]);
+        }
+    }
+
+    mod split_header {
+        use super::super::*;
+
+        #[test]
+        fn splits_on_first_colon() {
+            assert_eq!(
+                split_header("Host: localhost:8000"),
+                Some(("Host", "localhost:8000"))
+            );
+            assert_eq!(split_header("X-Empty:"), Some(("X-Empty", "")));
+            assert_eq!(split_header("Accept:\t*/* \t"), Some(("Accept", "*/*")));
+        }
+
+        #[test]
+        fn rejects_lines_without_name() {
+            assert_eq!(split_header("no colon here"), None);
+            assert_eq!(split_header(": value"), None);
+        }
+
+        #[test]
+        fn rejects_whitespace_in_name() {
+            assert_eq!(split_header("Transfer-Encoding : chunked"), None);
+            assert_eq!(split_header(" Transfer-Encoding: chunked"), None);
+            assert_eq!(split_header("\tX-Folded: value"), None);
+            assert_eq!(split_header("Bad Name: value"), None);
+        }
+
+        #[test]
+        fn rejects_control_characters_in_value() {
+            assert_eq!(split_header("X-Split: a\rb"), None);
+            assert_eq!(split_header("X-Nul: a\0b"), None);
+            assert_eq!(split_header("X-Tab: a\tb"), Some(("X-Tab", "a\tb")));
+        }
+    }
+}
@@ -1,188 +1,783 @@
-use std::{
-    collections::HashMap,
-    num::{ParseFloatError, ParseIntError},
-};
+use std::{collections::HashMap, fmt, io, vec};
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum JsonValue {
     Null,
     Bool(bool),
-    Float(f32),
-    Int(i32),
+    Float(f64),
+    Int(i64),
+    // the parser only uses it for integers above i64::MAX, either way it
+    // equals an `Int` with the same value
+    UInt(u64),
+    // the number exactly as written, see `ParseOptions::raw_numbers`
+    RawNumber(RawNumber),
     String(String),
     Array(Vec<JsonValue>),
-    Object(HashMap<String, Box<JsonValue>>),
+    Object(JsonObject),
 }
 
-impl ToString for JsonValue {
-    fn to_string(&self) -> String {
-        match &self {
-            Self::Null => "null".to_string(),
-            Self::Bool(true) => "true".to_string(),
-            Self::Bool(false) => "false".to_string(),
-            Self::Int(value) => value.to_string(),
-            Self::Float(value) => value.to_string(),
-            Self::String(value) => format!("\"{value}\""),
-            Self::Object(map) => {
-                let mut property_list = map
-                    .iter()
-                    .map(|(key, value)| format!("\"{key}\": {}", value.to_string()))
-                    .collect::<Vec<String>>();
-                property_list.sort();
-                format!("{{\r\n{}\r\n}}", property_list.join(",\r\n"))
-            }
+// a number lexeme that is known to match the JSON number grammar, it is
+// written out verbatim so anything else could change the document around it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawNumber(String);
+
+impl RawNumber {
+    pub fn new(lexeme: &str) -> Option<RawNumber> {
+        let options = ParseOptions::default().raw_numbers(true);
+        match parse_json_with(lexeme, &options) {
+            // surrounding whitespace would have been skipped by the parser
+            Ok(JsonValue::RawNumber(number)) if number.0 == lexeme => Some(number),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+// compact unless asked otherwise, `{:#}` pretty prints with two spaces
+impl fmt::Display for JsonValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format = match f.alternate() {
+            true => JsonFormat::pretty(2),
+            false => JsonFormat::compact(),
+        };
+        self.write(f, &format)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JsonFormat {
+    // spaces per nesting level, `None` puts everything on one line
+    pub indent: Option<usize>,
+    pub sort_keys: bool,
+}
+
+impl Default for JsonFormat {
+    fn default() -> JsonFormat {
+        JsonFormat::compact()
+    }
+}
+
+impl JsonFormat {
+    // keys come out in insertion order unless sorted
+    pub fn compact() -> JsonFormat {
+        JsonFormat {
+            indent: None,
+            sort_keys: false,
+        }
+    }
+
+    pub fn pretty(indent: usize) -> JsonFormat {
+        JsonFormat {
+            indent: Some(indent),
+            sort_keys: false,
+        }
+    }
+
+    pub fn sort_keys(mut self, sort_keys: bool) -> JsonFormat {
+        self.sort_keys = sort_keys;
+        self
+    }
+}
+
+impl JsonValue {
+    pub fn write<W: fmt::Write + ?Sized>(
+        &self,
+        writer: &mut W,
+        format: &JsonFormat,
+    ) -> fmt::Result {
+        self.write_nested(writer, format, 0)
+    }
+
+    pub fn write_io<W: io::Write>(&self, writer: &mut W, format: &JsonFormat) -> io::Result<()> {
+        let mut adapter = IoAdapter {
+            writer,
+            error: None,
+        };
+        match self.write(&mut adapter, format) {
+            Ok(()) => Ok(()),
+            Err(_) => Err(adapter
+                .error
+                .unwrap_or_else(|| io::Error::other("could not format JSON"))),
+        }
+    }
+
+    pub fn to_string_with(&self, format: &JsonFormat) -> String {
+        let mut output = String::new();
+        // writing into a String can't fail
+        let _ = self.write(&mut output, format);
+        output
+    }
+
+    fn write_nested<W: fmt::Write + ?Sized>(
+        &self,
+        writer: &mut W,
+        format: &JsonFormat,
+        depth: usize,
+    ) -> fmt::Result {
+        match self {
+            Self::Null => writer.write_str("null"),
+            Self::Bool(true) => writer.write_str("true"),
+            Self::Bool(false) => writer.write_str("false"),
+            Self::Int(value) => write!(writer, "{value}"),
+            Self::UInt(value) => write!(writer, "{value}"),
+            Self::RawNumber(number) => writer.write_str(number.as_str()),
+            // JSON has no NaN or infinity
+            Self::Float(value) if !value.is_finite() => writer.write_str("null"),
+            // Debug keeps the `.0` on whole numbers so they read back as floats
+            Self::Float(value) => write!(writer, "{value:?}"),
+            Self::String(value) => write_escaped(writer, value),
+            Self::Array(list) if list.is_empty() => writer.write_str("[]"),
             Self::Array(list) => {
-                format!(
-                    "[{}]",
-                    list.iter()
-                        .map(|value| value.to_string())
-                        .collect::<Vec<String>>()
-                        .join(", ")
-                )
+                writer.write_char('[')?;
+                for (i, value) in list.iter().enumerate() {
+                    write_separator(writer, format, depth + 1, i == 0)?;
+                    value.write_nested(writer, format, depth + 1)?;
+                }
+                write_line_break(writer, format, depth)?;
+                writer.write_char(']')
+            }
+            Self::Object(map) if map.is_empty() => writer.write_str("{}"),
+            Self::Object(map) => {
+                let mut entries: Vec<(&String, &JsonValue)> = map.iter().collect();
+                if format.sort_keys {
+                    entries.sort_by_key(|(key, _)| *key);
+                }
+
+                writer.write_char('{')?;
+                for (i, (key, value)) in entries.into_iter().enumerate() {
+                    write_separator(writer, format, depth + 1, i == 0)?;
+                    write_escaped(writer, key)?;
+                    writer.write_str(match format.indent {
+                        Some(_) => ": ",
+                        None => ":",
+                    })?;
+                    value.write_nested(writer, format, depth + 1)?;
+                }
+                write_line_break(writer, format, depth)?;
+                writer.write_char('}')
             }
         }
     }
 }
 
+// the comma before every item but the first, then a line break when
+// pretty printing
+fn write_separator<W: fmt::Write + ?Sized>(
+    writer: &mut W,
+    format: &JsonFormat,
+    depth: usize,
+    first: bool,
+) -> fmt::Result {
+    if !first {
+        writer.write_char(',')?;
+    }
+    write_line_break(writer, format, depth)
+}
+
+fn write_line_break<W: fmt::Write + ?Sized>(
+    writer: &mut W,
+    format: &JsonFormat,
+    depth: usize,
+) -> fmt::Result {
+    match format.indent {
+        Some(indent) => write!(writer, "\n{:width$}", "", width = indent * depth),
+        None => Ok(()),
+    }
+}
+
+fn write_escaped<W: fmt::Write + ?Sized>(writer: &mut W, value: &str) -> fmt::Result {
+    writer.write_char('"')?;
+    let mut run_start = 0;
+    for (i, char) in value.char_indices() {
+        let escaped = match char {
+            '"' => "\\\"",
+            '\\' => "\\\\",
+            '\n' => "\\n",
+            '\r' => "\\r",
+            '\t' => "\\t",
+            '\u{8}' => "\\b",
+            '\u{c}' => "\\f",
+            '\u{0}'..='\u{1f}' => "",
+            _ => continue,
+        };
+        writer.write_str(&value[run_start..i])?;
+        match escaped {
+            "" => write!(writer, "\\u{:04x}", char as u32)?,
+            escaped => writer.write_str(escaped)?,
+        }
+        run_start = i + char.len_utf8();
+    }
+    writer.write_str(&value[run_start..])?;
+    writer.write_char('"')
+}
+
+// fmt::Write can't carry an io::Error, it's kept aside for `write_io`
+struct IoAdapter<'a, W> {
+    writer: &'a mut W,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> fmt::Write for IoAdapter<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.writer.write_all(s.as_bytes()).map_err(|error| {
+            self.error = Some(error);
+            fmt::Error
+        })
+    }
+}
+
 impl PartialEq for JsonValue {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Self::Null, Self::Null) => true,
             (Self::Bool(l), Self::Bool(r)) => l == r,
             (Self::Int(l), Self::Int(r)) => l == r,
+            (Self::UInt(l), Self::UInt(r)) => l == r,
+            (Self::Int(l), Self::UInt(r)) | (Self::UInt(r), Self::Int(l)) => {
+                u64::try_from(*l).is_ok_and(|l| l == *r)
+            }
             (Self::Float(l), Self::Float(r)) => l == r,
+            (Self::RawNumber(l), Self::RawNumber(r)) => l == r,
             (Self::String(l), Self::String(r)) => l == r,
-            (Self::Array(l0), Self::Array(r0)) => l0
-                .iter()
-                .zip(r0.iter())
-                .fold(true, |equals, (l, r)| equals && (l == r)),
-            (Self::Object(left), Self::Object(right)) => {
-                left.iter().fold(true, |equals, (key, left_value)| {
-                    let right_value = match right.get(key) {
-                        Some(value) => value,
-                        None => return false,
-                    };
-                    equals && (left_value == right_value)
-                }) && right.iter().fold(true, |equals, (key, right_value)| {
-                    let left_value = match left.get(key) {
-                        Some(value) => value,
-                        None => return false,
-                    };
-                    equals && (left_value == right_value)
-                })
+            (Self::Array(l0), Self::Array(r0)) => {
+                l0.len() == r0.len()
+                    && l0
+                        .iter()
+                        .zip(r0.iter())
+                        .fold(true, |equals, (l, r)| equals && (l == r))
             }
+            (Self::Object(left), Self::Object(right)) => left == right,
             _ => false,
         }
     }
 }
 
-pub fn parse_json(json_str: &str) -> Option<JsonValue> {
-    // go through the json
-    let trimmed_str = json_str.trim();
-    let int_value: Result<i32, ParseIntError> = trimmed_str.parse();
-    let float_value: Result<f32, ParseFloatError> = trimmed_str.parse();
-    let null_value = trimmed_str == "null";
-    let boolean_value = trimmed_str == "true" || trimmed_str == "false";
-
-    match trimmed_str {
-        value if value.len() == 0 => None,
-
-        value if value.starts_with("{") && value.ends_with("}") => parse_object(value),
-        value if value.starts_with("[") && value.ends_with("]") => parse_array(value),
-        value if value.starts_with("\"") && value.ends_with("\"") => Some(JsonValue::String(
-            trimmed_str.to_string().replace(r#"""#, ""),
-        )),
-
-        _ if int_value.is_ok() => Some(JsonValue::Int(int_value.unwrap())),
-        _ if float_value.is_ok() => Some(JsonValue::Float(float_value.unwrap())),
-        _ if null_value => Some(JsonValue::Null),
-        _ if boolean_value => Some(JsonValue::Bool(trimmed_str == "true")),
-        _ => None,
-    }
+// object members in the order they were inserted, the index keeps key
+// lookups from turning quadratic on large payloads
+#[derive(Clone, Debug, Default)]
+pub struct JsonObject {
+    entries: Vec<(String, JsonValue)>,
+    index: HashMap<String, usize>,
 }
 
-fn split_json(json_str: &str) -> Option<Vec<&str>> {
-    let mut property_lines: Vec<&str> = Vec::new();
-    let mut symbol_stack: Vec<char> = Vec::new();
-    let mut start_idx: usize = 0;
-    let mut end_idx: usize = 0;
-    for (i, char) in json_str.chars().enumerate() {
-        match char {
-            '{' => symbol_stack.push('{'),
-            '}' if (*symbol_stack.last().unwrap_or(&'f') == '{') => {
-                symbol_stack.pop();
-            }
-            '}' => return None,
-            '[' => symbol_stack.push('['),
-            ']' if (*symbol_stack.last().unwrap_or(&'f') == '[') => {
-                symbol_stack.pop();
+impl JsonObject {
+    pub fn new() -> JsonObject {
+        JsonObject::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        let position = *self.index.get(key)?;
+        Some(&self.entries[position].1)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut JsonValue> {
+        let position = *self.index.get(key)?;
+        Some(&mut self.entries[position].1)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.index.contains_key(key)
+    }
+
+    // an existing key keeps its position and gets the new value
+    pub fn insert(&mut self, key: String, value: JsonValue) -> Option<JsonValue> {
+        match self.index.get(&key) {
+            Some(position) => Some(std::mem::replace(&mut self.entries[*position].1, value)),
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+                None
             }
-            ']' => return None,
-            ',' if symbol_stack.len() == 0 => {
-                property_lines.push(&json_str[start_idx..end_idx]);
-                start_idx = i + 1;
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<JsonValue> {
+        let position = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(position);
+        for (key, _) in &self.entries[position..] {
+            if let Some(later) = self.index.get_mut(key) {
+                *later -= 1;
             }
-            _ => (),
         }
-        end_idx = i + 1;
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &JsonValue)> {
+        self.entries.iter().map(|(key, value)| (key, value))
     }
 
-    if symbol_stack.len() > 0 {
-        return None;
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter().map(|(key, _)| key)
     }
 
-    if start_idx != end_idx {
-        property_lines.push(&json_str[start_idx..end_idx])
+    pub fn len(&self) -> usize {
+        self.entries.len()
     }
 
-    Some(property_lines)
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
 }
 
-fn parse_object(object_str: &str) -> Option<JsonValue> {
-    if object_str.len() < 2 || !object_str.starts_with("{") || !object_str.ends_with("}") {
-        return None;
-    };
-    let trimmed_object_str = &object_str[1..object_str.len() - 1];
-    let property_lines = match split_json(trimmed_object_str) {
-        Some(v) => v,
-        None => return None,
+// members are compared like a map, the order they're in doesn't matter
+impl PartialEq for JsonObject {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl FromIterator<(String, JsonValue)> for JsonObject {
+    fn from_iter<I: IntoIterator<Item = (String, JsonValue)>>(iter: I) -> JsonObject {
+        let mut object = JsonObject::new();
+        for (key, value) in iter {
+            object.insert(key, value);
+        }
+        object
+    }
+}
+
+impl<const N: usize> From<[(String, JsonValue); N]> for JsonObject {
+    fn from(entries: [(String, JsonValue); N]) -> JsonObject {
+        entries.into_iter().collect()
+    }
+}
+
+impl IntoIterator for JsonObject {
+    type Item = (String, JsonValue);
+    type IntoIter = vec::IntoIter<(String, JsonValue)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+// deeper documents are refused instead of risking the worker's stack
+const MAX_DEPTH: usize = 128;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JsonErrorKind {
+    UnexpectedToken,
+    UnexpectedEnd,
+    UnterminatedString,
+    InvalidEscape,
+    TrailingComma,
+    InvalidNumber,
+    TooDeep,
+    DuplicateKey,
+}
+
+impl fmt::Display for JsonErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            Self::UnexpectedToken => "unexpected token",
+            Self::UnexpectedEnd => "unexpected end of input",
+            Self::UnterminatedString => "unterminated string",
+            Self::InvalidEscape => "invalid escape sequence",
+            Self::TrailingComma => "trailing comma",
+            Self::InvalidNumber => "invalid number",
+            Self::TooDeep => "nesting too deep",
+            Self::DuplicateKey => "duplicate key",
+        };
+        write!(f, "{description}")
+    }
+}
+
+// `offset` counts bytes from the start of the input, `line` and `column`
+// start at 1 and columns count characters
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JsonError {
+    pub kind: JsonErrorKind,
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}",
+            self.kind, self.line, self.column
+        )
+    }
+}
+
+// RFC 8259 leaves repeated keys in an object up to the parser
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateKeys {
+    Error,
+    FirstWins,
+    // the value changes, the key stays where it first appeared
+    #[default]
+    LastWins,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    // keep every number as its original lexeme in `JsonValue::RawNumber`,
+    // for amounts and ids that mustn't go through a float
+    pub raw_numbers: bool,
+    pub duplicate_keys: DuplicateKeys,
+}
+
+impl ParseOptions {
+    pub fn raw_numbers(mut self, raw_numbers: bool) -> ParseOptions {
+        self.raw_numbers = raw_numbers;
+        self
+    }
+
+    pub fn duplicate_keys(mut self, duplicate_keys: DuplicateKeys) -> ParseOptions {
+        self.duplicate_keys = duplicate_keys;
+        self
+    }
+}
+
+pub fn parse_json(json_str: &str) -> Result<JsonValue, JsonError> {
+    parse_json_with(json_str, &ParseOptions::default())
+}
+
+// recursive descent over the grammar in RFC 8259, anything it doesn't
+// allow is rejected, including trailing content after the value
+pub fn parse_json_with(json_str: &str, options: &ParseOptions) -> Result<JsonValue, JsonError> {
+    let mut parser = Parser {
+        input: json_str,
+        bytes: json_str.as_bytes(),
+        position: 0,
+        depth: 0,
+        options,
     };
 
-    let property_map: HashMap<String, Box<JsonValue>> = property_lines
-        .iter()
-        .filter_map(|line| {
-            let (key, value) = match line.split_once(":") {
-                Some((key, value)) => (key, value),
-                None => return None,
-            };
-            let json_value = parse_json(value.trim());
-            match (key, json_value) {
-                (key, Some(value)) => {
-                    Some((key.trim().to_string().replace(r#"""#, ""), Box::new(value)))
+    parser.skip_whitespace();
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    match parser.peek() {
+        None => Ok(value),
+        Some(_) => Err(parser.error(JsonErrorKind::UnexpectedToken)),
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    bytes: &'a [u8],
+    position: usize,
+    depth: usize,
+    options: &'a ParseOptions,
+}
+
+impl Parser<'_> {
+    fn error(&self, kind: JsonErrorKind) -> JsonError {
+        self.error_at(kind, self.position)
+    }
+
+    fn error_at(&self, kind: JsonErrorKind, offset: usize) -> JsonError {
+        let before = &self.bytes[..offset];
+        let line_start = before
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map_or(0, |newline| newline + 1);
+        // `offset` may point into the middle of a character, count it anyway
+        let column = String::from_utf8_lossy(&before[line_start..])
+            .chars()
+            .count()
+            + 1;
+        JsonError {
+            kind,
+            offset,
+            line: before.iter().filter(|byte| **byte == b'\n').count() + 1,
+            column,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+
+    // the next byte, running out of input is an error
+    fn current(&self) -> Result<u8, JsonError> {
+        self.peek()
+            .ok_or_else(|| self.error(JsonErrorKind::UnexpectedEnd))
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<(), JsonError> {
+        if self.current()? != expected {
+            return Err(self.error(JsonErrorKind::UnexpectedToken));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    // only these four count as whitespace, not the rest of Unicode's
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<JsonValue, JsonError> {
+        match self.current()? {
+            b'{' => self.nested(Parser::parse_object),
+            b'[' => self.nested(Parser::parse_array),
+            b'"' => self.parse_string().map(JsonValue::String),
+            b'-' | b'0'..=b'9' => self.parse_number(),
+            b't' => self.parse_literal("true", JsonValue::Bool(true)),
+            b'f' => self.parse_literal("false", JsonValue::Bool(false)),
+            b'n' => self.parse_literal("null", JsonValue::Null),
+            _ => Err(self.error(JsonErrorKind::UnexpectedToken)),
+        }
+    }
+
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<JsonValue, JsonError>,
+    ) -> Result<JsonValue, JsonError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error(JsonErrorKind::TooDeep));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn parse_literal(&mut self, literal: &str, value: JsonValue) -> Result<JsonValue, JsonError> {
+        for expected in literal.bytes() {
+            self.expect(expected)?;
+        }
+        Ok(value)
+    }
+
+    // `[` or `{` up to and including the matching close, `item` parses
+    // whatever sits between two commas
+    fn parse_list<F>(&mut self, open: u8, close: u8, mut item: F) -> Result<(), JsonError>
+    where
+        F: FnMut(&mut Self) -> Result<(), JsonError>,
+    {
+        self.expect(open)?;
+        self.skip_whitespace();
+        if self.current()? == close {
+            self.position += 1;
+            return Ok(());
+        }
+
+        loop {
+            self.skip_whitespace();
+            item(self)?;
+
+            self.skip_whitespace();
+            match self.current()? {
+                b',' => {
+                    let comma = self.position;
+                    self.position += 1;
+                    self.skip_whitespace();
+                    if self.peek() == Some(close) {
+                        return Err(self.error_at(JsonErrorKind::TrailingComma, comma));
+                    }
+                }
+                byte if byte == close => {
+                    self.position += 1;
+                    return Ok(());
                 }
-                _ => None,
+                _ => return Err(self.error(JsonErrorKind::UnexpectedToken)),
             }
-        })
-        .collect();
+        }
+    }
 
-    Some(JsonValue::Object(property_map))
-}
+    fn parse_object(&mut self) -> Result<JsonValue, JsonError> {
+        let mut map = JsonObject::new();
+        self.parse_list(b'{', b'}', |parser| {
+            if parser.current()? != b'"' {
+                return Err(parser.error(JsonErrorKind::UnexpectedToken));
+            }
+            let key_start = parser.position;
+            let key = parser.parse_string()?;
+            parser.skip_whitespace();
+            parser.expect(b':')?;
+            parser.skip_whitespace();
+            let value = parser.parse_value()?;
 
-fn parse_array(array_str: &str) -> Option<JsonValue> {
-    if array_str.len() < 2 || !array_str.starts_with("[") || !array_str.ends_with("]") {
-        return None;
-    };
-    let trimmed_array_str = &array_str[1..array_str.len() - 1];
-    let values = match split_json(trimmed_array_str) {
-        Some(v) => v,
-        None => return None,
-    };
-    let json_values: Vec<JsonValue> = values
-        .iter()
-        .filter_map(|value| parse_json(value))
-        .collect();
+            match parser.options.duplicate_keys {
+                _ if !map.contains_key(&key) => {
+                    map.insert(key, value);
+                }
+                DuplicateKeys::Error => {
+                    return Err(parser.error_at(JsonErrorKind::DuplicateKey, key_start))
+                }
+                DuplicateKeys::FirstWins => (),
+                DuplicateKeys::LastWins => {
+                    map.insert(key, value);
+                }
+            }
+            Ok(())
+        })?;
+        Ok(JsonValue::Object(map))
+    }
+
+    fn parse_array(&mut self) -> Result<JsonValue, JsonError> {
+        let mut list = Vec::new();
+        self.parse_list(b'[', b']', |parser| {
+            list.push(parser.parse_value()?);
+            Ok(())
+        })?;
+        Ok(JsonValue::Array(list))
+    }
+
+    fn parse_string(&mut self) -> Result<String, JsonError> {
+        let start = self.position;
+        self.expect(b'"')?;
+        let mut value = String::new();
+        // unescaped runs are copied in one go, they only ever end on ASCII
+        // bytes so slicing `input` stays on character boundaries
+        let mut run_start = self.position;
 
-    Some(JsonValue::Array(json_values))
+        loop {
+            match self.peek() {
+                None => return Err(self.error_at(JsonErrorKind::UnterminatedString, start)),
+                Some(b'"') => {
+                    value.push_str(&self.input[run_start..self.position]);
+                    self.position += 1;
+                    return Ok(value);
+                }
+                Some(b'\\') => {
+                    value.push_str(&self.input[run_start..self.position]);
+                    value.push(self.parse_escape()?);
+                    run_start = self.position;
+                }
+                // control characters have to be escaped
+                Some(0x00..=0x1f) => return Err(self.error(JsonErrorKind::UnexpectedToken)),
+                Some(_) => self.position += 1,
+            }
+        }
+    }
+
+    fn parse_escape(&mut self) -> Result<char, JsonError> {
+        let start = self.position;
+        self.position += 1;
+        let escaped = match self.peek() {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                self.position += 1;
+                return self
+                    .parse_unicode_escape()
+                    .ok_or_else(|| self.error_at(JsonErrorKind::InvalidEscape, start));
+            }
+            _ => return Err(self.error_at(JsonErrorKind::InvalidEscape, start)),
+        };
+        self.position += 1;
+        Ok(escaped)
+    }
+
+    // characters outside the basic multilingual plane come as a UTF-16
+    // surrogate pair, a lone surrogate isn't a character and is refused
+    fn parse_unicode_escape(&mut self) -> Option<char> {
+        let high = self.parse_hex4()?;
+        let code_point = match high {
+            0xd800..=0xdbff => {
+                if self.input.get(self.position..self.position + 2) != Some("\\u") {
+                    return None;
+                }
+                self.position += 2;
+                let low = self.parse_hex4()?;
+                if !(0xdc00..=0xdfff).contains(&low) {
+                    return None;
+                }
+                0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+            }
+            0xdc00..=0xdfff => return None,
+            code_point => code_point,
+        };
+        char::from_u32(code_point)
+    }
+
+    fn parse_hex4(&mut self) -> Option<u32> {
+        let digits = self.input.get(self.position..self.position + 4)?;
+        if !digits.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return None;
+        }
+        self.position += 4;
+        u32::from_str_radix(digits, 16).ok()
+    }
+
+    // -? (0 | [1-9][0-9]*) (. [0-9]+)? ([eE] [+-]? [0-9]+)?
+    fn parse_number(&mut self) -> Result<JsonValue, JsonError> {
+        let start = self.position;
+        let invalid = |parser: &Self| parser.error_at(JsonErrorKind::InvalidNumber, start);
+
+        if self.peek() == Some(b'-') {
+            self.position += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.position += 1,
+            Some(b'1'..=b'9') => {
+                self.skip_digits();
+            }
+            _ => return Err(invalid(self)),
+        }
+
+        let mut integer = true;
+        if self.peek() == Some(b'.') {
+            integer = false;
+            self.position += 1;
+            if !self.skip_digits() {
+                return Err(invalid(self));
+            }
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            integer = false;
+            self.position += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.position += 1;
+            }
+            if !self.skip_digits() {
+                return Err(invalid(self));
+            }
+        }
+        // `01` or `1x` would otherwise read as a number followed by junk
+        if let Some(b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' | b'.') = self.peek() {
+            return Err(invalid(self));
+        }
+
+        let lexeme = &self.input[start..self.position];
+        if self.options.raw_numbers {
+            return Ok(JsonValue::RawNumber(RawNumber(lexeme.to_string())));
+        }
+        if integer {
+            if let Ok(value) = lexeme.parse() {
+                return Ok(JsonValue::Int(value));
+            }
+            if let Ok(value) = lexeme.parse() {
+                return Ok(JsonValue::UInt(value));
+            }
+        }
+        // integers beyond u64 still make a (lossy) float
+        lexeme
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .map(JsonValue::Float)
+            .ok_or_else(|| invalid(self))
+    }
+
+    // whether there was at least one digit
+    fn skip_digits(&mut self) -> bool {
+        let start = self.position;
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.position += 1;
+        }
+        self.position > start
+    }
 }
 
 #[cfg(test)]
@@ -191,12 +786,15 @@ mod tests {
         use super::super::*;
         #[test]
         fn empty_string() {
-            assert!(parse_json("").is_none())
+            assert!(parse_json("").is_err())
         }
 
         #[test]
         fn empty_object() {
-            assert_eq!(parse_json("{}").unwrap(), JsonValue::Object(HashMap::new()))
+            assert_eq!(
+                parse_json("{}").unwrap(),
+                JsonValue::Object(JsonObject::new())
+            )
         }
 
         #[test]
@@ -208,10 +806,7 @@ mod tests {
         fn one_dimensional_object() {
             assert_eq!(
                 parse_json(r#"{"a": null}"#).unwrap(),
-                JsonValue::Object(HashMap::from([(
-                    "a".to_string(),
-                    Box::new(JsonValue::Null)
-                )]))
+                JsonValue::Object(JsonObject::from([("a".to_string(), JsonValue::Null)]))
             )
         }
 
@@ -219,12 +814,9 @@ mod tests {
         fn two_dimensional_object() {
             assert_eq!(
                 parse_json(r#"{"a": {"b": null}}"#).unwrap(),
-                JsonValue::Object(HashMap::from([(
+                JsonValue::Object(JsonObject::from([(
                     "a".to_string(),
-                    Box::new(JsonValue::Object(HashMap::from([(
-                        "b".to_string(),
-                        Box::new(JsonValue::Null)
-                    )])))
+                    JsonValue::Object(JsonObject::from([("b".to_string(), JsonValue::Null)]))
                 )]))
             )
         }
@@ -234,20 +826,15 @@ mod tests {
             assert_eq!(
                 parse_json(r#"{"a": null, "b": 7, "c": "hello", "d": ["my life"], "e": false}"#)
                     .unwrap(),
-                JsonValue::Object(HashMap::from([
-                    ("a".to_string(), Box::new(JsonValue::Null)),
-                    ("b".to_string(), Box::new(JsonValue::Int(7))),
-                    (
-                        "c".to_string(),
-                        Box::new(JsonValue::String("hello".to_string()))
-                    ),
+                JsonValue::Object(JsonObject::from([
+                    ("a".to_string(), JsonValue::Null),
+                    ("b".to_string(), JsonValue::Int(7)),
+                    ("c".to_string(), JsonValue::String("hello".to_string())),
                     (
                         "d".to_string(),
-                        Box::new(JsonValue::Array(vec![JsonValue::String(
-                            "my life".to_string()
-                        )]))
+                        JsonValue::Array(vec![JsonValue::String("my life".to_string())])
                     ),
-                    ("e".to_string(), Box::new(JsonValue::Bool(false)))
+                    ("e".to_string(), JsonValue::Bool(false))
                 ]))
             )
         }
@@ -268,89 +855,419 @@ mod tests {
 
         #[test]
         fn bad_formatting() {
-            assert_eq!(parse_json("{}}}"), None)
+            assert_eq!(
+                parse_json("{}}}").unwrap_err().kind,
+                JsonErrorKind::UnexpectedToken
+            )
+        }
+
+        #[test]
+        fn separators_inside_strings() {
+            assert_eq!(
+                parse_json(r#"{"a": "x,y", "url": "http://x", "b": "[{]}"}"#).unwrap(),
+                JsonValue::Object(JsonObject::from([
+                    ("a".to_string(), JsonValue::String("x,y".to_string())),
+                    ("url".to_string(), JsonValue::String("http://x".to_string())),
+                    ("b".to_string(), JsonValue::String("[{]}".to_string())),
+                ]))
+            )
+        }
+
+        #[test]
+        fn string_escapes() {
+            assert_eq!(
+                parse_json(r#""a\"b\\c\/d\b\f\n\r\t""#).unwrap(),
+                JsonValue::String("a\"b\\c/d\u{8}\u{c}\n\r\t".to_string())
+            );
+            assert_eq!(
+                parse_json(r#""\u00e9\u20AC\ud83d\ude00""#).unwrap(),
+                JsonValue::String("é€😀".to_string())
+            );
+        }
+
+        #[test]
+        fn numbers() {
+            assert_eq!(parse_json("-0").unwrap(), JsonValue::Int(0));
+            assert_eq!(parse_json("1.5e3").unwrap(), JsonValue::Float(1500.0));
+            assert_eq!(parse_json("2E-2").unwrap(), JsonValue::Float(0.02));
+            assert_eq!(parse_json("1e+2").unwrap(), JsonValue::Float(100.0));
+        }
+
+        #[test]
+        fn wide_numbers() {
+            assert_eq!(
+                parse_json("9007199254740993").unwrap(),
+                JsonValue::Int(9_007_199_254_740_993)
+            );
+            assert_eq!(
+                parse_json("-9223372036854775808").unwrap(),
+                JsonValue::Int(i64::MIN)
+            );
+            assert_eq!(
+                parse_json("18446744073709551615").unwrap(),
+                JsonValue::UInt(u64::MAX)
+            );
+            assert_eq!(
+                parse_json("18446744073709551616").unwrap(),
+                JsonValue::Float(18446744073709551616.0)
+            );
+            assert_eq!(parse_json("0.1").unwrap(), JsonValue::Float(0.1));
+        }
+
+        #[test]
+        fn integers_compare_by_value() {
+            assert_eq!(parse_json("5").unwrap(), JsonValue::UInt(5));
+            assert_eq!(JsonValue::UInt(5), JsonValue::Int(5));
+            assert_ne!(JsonValue::Int(-1), JsonValue::UInt(u64::MAX));
+            let value = JsonValue::Array(vec![JsonValue::UInt(0), JsonValue::UInt(u64::MAX)]);
+            assert_eq!(parse_json(&value.to_string()).unwrap(), value);
+        }
+
+        #[test]
+        fn raw_numbers() {
+            let options = ParseOptions::default().raw_numbers(true);
+            let json = r#"[12345678901234567890123, 0.10, -1.5E+3, 7]"#;
+            let value = parse_json_with(json, &options).unwrap();
+            assert_eq!(
+                value,
+                JsonValue::Array(vec![
+                    JsonValue::RawNumber(RawNumber::new("12345678901234567890123").unwrap()),
+                    JsonValue::RawNumber(RawNumber::new("0.10").unwrap()),
+                    JsonValue::RawNumber(RawNumber::new("-1.5E+3").unwrap()),
+                    JsonValue::RawNumber(RawNumber::new("7").unwrap()),
+                ])
+            );
+            assert_eq!(
+                value.to_string(),
+                "[12345678901234567890123,0.10,-1.5E+3,7]"
+            );
+            // the grammar is enforced all the same
+            assert!(parse_json_with("[01]", &options).is_err());
+        }
+
+        #[test]
+        fn raw_number_lexemes() {
+            assert_eq!(RawNumber::new("-0.5e10").unwrap().as_str(), "-0.5e10");
+            for lexeme in [
+                "1,\"x\":2",
+                " 1",
+                "1 ",
+                "01",
+                "+1",
+                "1.",
+                "",
+                "\"1\"",
+                "[1]",
+            ] {
+                assert_eq!(RawNumber::new(lexeme), None, "accepted {lexeme:?}");
+            }
+        }
+
+        // a selection of the y_ and n_ cases from JSONTestSuite
+        #[test]
+        fn accepts_valid_documents() {
+            for json in [
+                "[[]   ]",
+                " [1] ",
+                "[\"\"]",
+                "[-1]",
+                "[0e1]",
+                "[1E22]",
+                "[123e-10000000]",
+                "{\"a\":\"b\",\"a\":\"c\"}",
+                "{\"\":0}",
+                "[\"\\u0000\"]",
+                "[\"\\uD834\\uDd1e\"]",
+                "[\"𝄞\"]",
+                "\"\\u0061\\u30af\\u30EA\\u30b9\"",
+                "[null, false, true]",
+                "\t\n\r 2 ",
+            ] {
+                assert!(parse_json(json).is_ok(), "rejected {json}");
+            }
+        }
+
+        #[test]
+        fn rejects_invalid_documents() {
+            for json in [
+                "[1,]",
+                "[,1]",
+                "[1,,2]",
+                "{\"a\":1,}",
+                "{\"a\" 1}",
+                "{a: 1}",
+                "{'a': 1}",
+                "[\"a\"",
+                "[01]",
+                "[-]",
+                "[.5]",
+                "[1.]",
+                "[1e]",
+                "[+1]",
+                "[0x1]",
+                "[NaN]",
+                "[Infinity]",
+                "[1e400]",
+                "[tru]",
+                "[True]",
+                "[\"\\x00\"]",
+                "[\"\\u12\"]",
+                "[\"\\uD800\"]",
+                "[\"\\uDC00\\uD800\"]",
+                "[\"a\tb\"]",
+                "[\"\\'\"]",
+                "[1] [2]",
+                "[1]x",
+                "\u{a0}[1]",
+                "[\"\u{0}\"]",
+                "",
+                " ",
+            ] {
+                assert!(parse_json(json).is_err(), "accepted {json}");
+            }
+        }
+
+        #[test]
+        fn limits_nesting() {
+            let nested = |depth| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+            assert!(parse_json(&nested(MAX_DEPTH)).is_ok());
+            assert_eq!(
+                parse_json(&nested(MAX_DEPTH + 1)).unwrap_err().kind,
+                JsonErrorKind::TooDeep
+            );
+            assert!(parse_json(&"[".repeat(100_000)).is_err());
+        }
+    }
+
+    mod json_object {
+        use super::super::*;
+
+        #[test]
+        fn insert_keeps_position() {
+            let mut object = JsonObject::from([
+                ("b".to_string(), JsonValue::Int(1)),
+                ("a".to_string(), JsonValue::Int(2)),
+            ]);
+            assert_eq!(
+                object.insert("b".to_string(), JsonValue::Int(3)),
+                Some(JsonValue::Int(1))
+            );
+            assert_eq!(object.insert("c".to_string(), JsonValue::Int(4)), None);
+            assert_eq!(object.keys().collect::<Vec<_>>(), ["b", "a", "c"]);
+            assert_eq!(object.get("b"), Some(&JsonValue::Int(3)));
+        }
+
+        #[test]
+        fn remove_reindexes() {
+            let mut object: JsonObject = ["a", "b", "c"]
+                .into_iter()
+                .map(|key| (key.to_string(), JsonValue::Null))
+                .collect();
+            assert_eq!(object.remove("a"), Some(JsonValue::Null));
+            assert_eq!(object.remove("a"), None);
+            *object.get_mut("c").unwrap() = JsonValue::Bool(true);
+            assert_eq!(object.get("c"), Some(&JsonValue::Bool(true)));
+            assert_eq!(object.keys().collect::<Vec<_>>(), ["b", "c"]);
+            assert_eq!(object.len(), 2);
+        }
+
+        #[test]
+        fn equality_ignores_order() {
+            assert_eq!(
+                parse_json(r#"{"a":1,"b":2}"#).unwrap(),
+                parse_json(r#"{"b":2,"a":1}"#).unwrap()
+            );
+            assert_ne!(
+                parse_json(r#"{"a":1}"#).unwrap(),
+                parse_json(r#"{"a":1,"b":2}"#).unwrap()
+            );
+        }
+
+        #[test]
+        fn duplicate_keys() {
+            let json = r#"{"a":1,"b":2,"a":3}"#;
+            let parse = |policy| {
+                parse_json_with(json, &ParseOptions::default().duplicate_keys(policy))
+                    .map(|value| value.to_string())
+            };
+            assert_eq!(parse(DuplicateKeys::LastWins).unwrap(), r#"{"a":3,"b":2}"#);
+            assert_eq!(parse(DuplicateKeys::FirstWins).unwrap(), r#"{"a":1,"b":2}"#);
+            let error = parse(DuplicateKeys::Error).unwrap_err();
+            assert_eq!(
+                (error.kind, error.offset),
+                (JsonErrorKind::DuplicateKey, 13)
+            );
+            assert_eq!(parse_json(json).unwrap().to_string(), r#"{"a":3,"b":2}"#);
+        }
+    }
+
+    mod json_error {
+        use super::super::*;
+
+        fn error(json: &str) -> (JsonErrorKind, usize, usize, usize) {
+            let error = parse_json(json).unwrap_err();
+            (error.kind, error.offset, error.line, error.column)
+        }
+
+        #[test]
+        fn kinds_and_positions() {
+            assert_eq!(error("[1, 2,]"), (JsonErrorKind::TrailingComma, 5, 1, 6));
+            assert_eq!(
+                error("{\"a\": 1,\n  }"),
+                (JsonErrorKind::TrailingComma, 7, 1, 8)
+            );
+            assert_eq!(
+                error("{\n  \"a\": \"open\n}"),
+                (JsonErrorKind::UnexpectedToken, 14, 2, 13)
+            );
+            assert_eq!(
+                error("[\"open"),
+                (JsonErrorKind::UnterminatedString, 1, 1, 2)
+            );
+            assert_eq!(
+                error("{\"a\":\n -01}"),
+                (JsonErrorKind::InvalidNumber, 7, 2, 2)
+            );
+            assert_eq!(error("[1e]"), (JsonErrorKind::InvalidNumber, 1, 1, 2));
+            assert_eq!(error("[\"\\q\"]"), (JsonErrorKind::InvalidEscape, 2, 1, 3));
+            assert_eq!(error("[1"), (JsonErrorKind::UnexpectedEnd, 2, 1, 3));
+            assert_eq!(error("[1] x"), (JsonErrorKind::UnexpectedToken, 4, 1, 5));
+        }
+
+        #[test]
+        fn columns_count_characters() {
+            assert_eq!(
+                error("[\"é€\", x]"),
+                (JsonErrorKind::UnexpectedToken, 10, 1, 8)
+            );
+        }
+
+        #[test]
+        fn display() {
+            assert_eq!(
+                parse_json("[1,]").unwrap_err().to_string(),
+                "trailing comma at line 1, column 3"
+            );
         }
     }
 
     mod json_str {
         use super::super::*;
 
+        fn sample() -> JsonValue {
+            JsonValue::Object(JsonObject::from([
+                ("a".to_string(), JsonValue::Null),
+                ("b".to_string(), JsonValue::Int(7)),
+                ("c".to_string(), JsonValue::String("hello".to_string())),
+                (
+                    "d".to_string(),
+                    JsonValue::Array(vec![JsonValue::String("my life".to_string())]),
+                ),
+                ("e".to_string(), JsonValue::Bool(false)),
+                (
+                    "f".to_string(),
+                    JsonValue::Object(JsonObject::from([("g".to_string(), JsonValue::Null)])),
+                ),
+            ]))
+        }
+
         #[test]
         fn primitive() {
             assert_eq!("\"hi\"", JsonValue::String("hi".to_string()).to_string());
             assert_eq!("1", JsonValue::Int(1).to_string());
             assert_eq!("1.1", JsonValue::Float(1.1).to_string());
+            assert_eq!("10.0", JsonValue::Float(10.0).to_string());
+            assert_eq!("0.1", JsonValue::Float(0.1).to_string());
+            assert_eq!("1e300", JsonValue::Float(1e300).to_string());
+            assert_eq!("-9223372036854775808", JsonValue::Int(i64::MIN).to_string());
+            assert_eq!(
+                "18446744073709551615",
+                JsonValue::UInt(u64::MAX).to_string()
+            );
+            assert_eq!("null", JsonValue::Float(f64::NAN).to_string());
             assert_eq!("null", JsonValue::Null.to_string());
             assert_eq!("true", JsonValue::Bool(true).to_string());
             assert_eq!("false", JsonValue::Bool(false).to_string());
         }
 
         #[test]
-        fn one_dimensional_object() {
+        fn escapes_strings() {
             assert_eq!(
-                "{\r\n\"a\": null,\r\n\"b\": 7,\r\n\"c\": \"hello\",\r\n\"d\": [\"my life\"],\r\n\"e\": false\r\n}",
-                JsonValue::Object(HashMap::from([
-                    ("a".to_string(), Box::new(JsonValue::Null)),
-                    ("b".to_string(), Box::new(JsonValue::Int(7))),
-                    (
-                        "c".to_string(),
-                        Box::new(JsonValue::String("hello".to_string()))
-                    ),
-                    (
-                        "d".to_string(),
-                        Box::new(JsonValue::Array(vec![JsonValue::String(
-                            "my life".to_string()
-                        )]))
-                    ),
-                    ("e".to_string(), Box::new(JsonValue::Bool(false)))
-                ]))
-                .to_string()
-            )
+                JsonValue::String("say \"hi\"\\\n\t\u{1}\u{8}é😀</".to_string()).to_string(),
+                r#""say \"hi\"\\\n\t\u0001\bé😀</""#
+            );
+            let key = JsonValue::Object(JsonObject::from([("a\"b".to_string(), JsonValue::Null)]));
+            assert_eq!(key.to_string(), r#"{"a\"b":null}"#);
         }
 
         #[test]
-        fn one_dimensional_array() {
+        fn round_trips_through_parser() {
+            let value = JsonValue::String("\"\\\u{0}\u{1f}\r\u{2028}".to_string());
+            assert_eq!(parse_json(&value.to_string()).unwrap(), value);
+            assert_eq!(parse_json(&sample().to_string()).unwrap(), sample());
+            assert_eq!(parse_json(&format!("{:#}", sample())).unwrap(), sample());
+        }
+
+        #[test]
+        fn compact_by_default() {
+            assert_eq!(
+                sample().to_string(),
+                r#"{"a":null,"b":7,"c":"hello","d":["my life"],"e":false,"f":{"g":null}}"#
+            );
             assert_eq!(
-                "[\"test string\", 10, 10.1, null, true]",
                 JsonValue::Array(vec![
                     JsonValue::String("test string".to_string()),
                     JsonValue::Int(10),
                     JsonValue::Float(10.1),
                     JsonValue::Null,
-                    JsonValue::Bool(true)
+                    JsonValue::Array(vec![]),
+                    JsonValue::Object(JsonObject::new()),
                 ])
-                .to_string()
-            )
+                .to_string(),
+                r#"["test string",10,10.1,null,[],{}]"#
+            );
         }
 
         #[test]
-        fn two_dimensional_object() {
+        fn pretty() {
             assert_eq!(
-                "{\r\n\"a\": null,\r\n\"b\": 7,\r\n\"c\": \"hello\",\r\n\"d\": [\"my life\"],\r\n\"e\": false,\r\n\"f\": {\r\n\"g\": null\r\n}\r\n}",
-                JsonValue::Object(HashMap::from([
-                    ("a".to_string(), Box::new(JsonValue::Null)),
-                    ("b".to_string(), Box::new(JsonValue::Int(7))),
-                    (
-                        "c".to_string(),
-                        Box::new(JsonValue::String("hello".to_string()))
-                    ),
-                    (
-                        "d".to_string(),
-                        Box::new(JsonValue::Array(vec![JsonValue::String(
-                            "my life".to_string()
-                        )]))
-                    ),
-                    ("e".to_string(), Box::new(JsonValue::Bool(false))),
-                    (
-                        "f".to_string(),
-                        Box::new(JsonValue::Object(HashMap::from([(
-                            "g".to_string(),
-                            Box::new(JsonValue::Null)
-                        )])))
-                    )
-                ]))
-                .to_string()
-            )
+                sample().to_string_with(&JsonFormat::pretty(4)),
+                "{\n    \"a\": null,\n    \"b\": 7,\n    \"c\": \"hello\",\n    \"d\": [\n        \"my life\"\n    ],\n    \"e\": false,\n    \"f\": {\n        \"g\": null\n    }\n}"
+            );
+            assert_eq!(
+                format!(
+                    "{:#}",
+                    JsonValue::Array(vec![JsonValue::Int(1), JsonValue::Array(vec![])])
+                ),
+                "[\n  1,\n  []\n]"
+            );
+        }
+
+        #[test]
+        fn keeps_insertion_order() {
+            let json = r#"{"z":1,"a":{"y":2,"b":3},"m":4}"#;
+            assert_eq!(parse_json(json).unwrap().to_string(), json);
+            assert_eq!(
+                parse_json(json)
+                    .unwrap()
+                    .to_string_with(&JsonFormat::compact().sort_keys(true)),
+                r#"{"a":{"b":3,"y":2},"m":4,"z":1}"#
+            );
+        }
+
+        #[test]
+        fn writes_to_io() {
+            let mut output: Vec<u8> = Vec::new();
+            sample()
+                .write_io(&mut output, &JsonFormat::compact())
+                .unwrap();
+            assert_eq!(output, sample().to_string().into_bytes());
+
+            let mut full = [0; 4];
+            let error = sample()
+                .write_io(&mut full.as_mut_slice(), &JsonFormat::compact())
+                .unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::WriteZero);
         }
     }
 }
@@ -0,0 +1,542 @@
+use std::{
+    env, fs,
+    fs::File,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use super::{
+    headers::{self, HeaderMap},
+    request,
+};
+
+const READ_SIZE: usize = 8192;
+const MAX_HEADER_LINE: usize = 8192;
+const MAX_PART_HEADERS: usize = 32;
+
+#[derive(Clone, Debug)]
+pub struct MultipartLimits {
+    // applies to every single part, files and text fields alike
+    pub max_part_size: u64,
+    pub max_total_size: u64,
+    // file parts growing past this many bytes move from memory to a temp
+    // file, `None` keeps everything in memory
+    pub spool_threshold: Option<u64>,
+    // defaults to the system temp directory
+    pub spool_dir: Option<PathBuf>,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> MultipartLimits {
+        MultipartLimits {
+            max_part_size: 10 * 1024 * 1024,
+            max_total_size: 50 * 1024 * 1024,
+            spool_threshold: Some(1024 * 1024),
+            spool_dir: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MultipartError {
+    Malformed,
+    TooLarge,
+    TimedOut,
+    // writing a spooled part failed, not the client's fault
+    Storage(io::Error),
+}
+
+pub struct Part {
+    pub name: String,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub headers: HeaderMap,
+    pub data: PartData,
+}
+
+pub enum PartData {
+    Memory(Vec<u8>),
+    File(SpooledFile),
+}
+
+impl Part {
+    pub fn size(&self) -> u64 {
+        match &self.data {
+            PartData::Memory(bytes) => bytes.len() as u64,
+            PartData::File(file) => file.size,
+        }
+    }
+
+    pub fn reader(&self) -> io::Result<Box<dyn Read + '_>> {
+        match &self.data {
+            PartData::Memory(bytes) => Ok(Box::new(bytes.as_slice())),
+            PartData::File(file) => Ok(Box::new(File::open(&file.path)?)),
+        }
+    }
+
+    pub fn bytes(&self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.reader()?.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+// removed from disk again once the request is dropped
+pub struct SpooledFile {
+    path: PathBuf,
+    size: u64,
+}
+
+impl SpooledFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SpooledFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+enum Sink {
+    Memory(Vec<u8>),
+    File(SpooledFile, File),
+}
+
+impl Sink {
+    fn write(
+        &mut self,
+        data: &[u8],
+        spool: bool,
+        limits: &MultipartLimits,
+    ) -> Result<(), MultipartError> {
+        if let Sink::Memory(bytes) = self {
+            let over_threshold = limits
+                .spool_threshold
+                .is_some_and(|threshold| (bytes.len() + data.len()) as u64 > threshold);
+            if spool && over_threshold {
+                let (mut spooled, mut file) =
+                    create_spool_file(limits).map_err(MultipartError::Storage)?;
+                file.write_all(bytes).map_err(MultipartError::Storage)?;
+                spooled.size = bytes.len() as u64;
+                *self = Sink::File(spooled, file);
+            }
+        }
+
+        match self {
+            Sink::Memory(bytes) => bytes.extend_from_slice(data),
+            Sink::File(spooled, file) => {
+                file.write_all(data).map_err(MultipartError::Storage)?;
+                spooled.size += data.len() as u64;
+            }
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        match self {
+            Sink::Memory(bytes) => bytes.len() as u64,
+            Sink::File(spooled, _) => spooled.size,
+        }
+    }
+
+    fn finish(self) -> Result<PartData, MultipartError> {
+        match self {
+            Sink::Memory(bytes) => Ok(PartData::Memory(bytes)),
+            Sink::File(spooled, mut file) => {
+                file.flush().map_err(MultipartError::Storage)?;
+                Ok(PartData::File(spooled))
+            }
+        }
+    }
+}
+
+fn create_spool_file(limits: &MultipartLimits) -> io::Result<(SpooledFile, File)> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let directory = limits.spool_dir.clone().unwrap_or_else(env::temp_dir);
+    let path = directory.join(format!(
+        "rust_web-upload-{}-{}",
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    Ok((SpooledFile { path, size: 0 }, file))
+}
+
+// `boundary` parameter of a multipart/form-data Content-Type (RFC 2046 section 5.1.1)
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let media_type = params.next()?.trim();
+    if !media_type.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+
+    let boundary = params.find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"').to_string())
+    })?;
+    (1..=70).contains(&boundary.len()).then_some(boundary)
+}
+
+// reads the whole body from `reader` without holding more than a few
+// kilobytes of it at once, apart from the parts kept in memory
+pub fn parse_multipart<R: Read>(
+    reader: R,
+    boundary: &str,
+    limits: &MultipartLimits,
+) -> Result<Vec<Part>, MultipartError> {
+    // a leading CRLF lets the very first delimiter match like every other one
+    let mut scanner = Scanner {
+        reader,
+        buffer: b"\r\n".to_vec(),
+        read_total: 0,
+        max_total: limits.max_total_size,
+    };
+    let delimiter = format!("\r\n--{boundary}").into_bytes();
+    let mut parts = Vec::new();
+
+    scanner.read_until(&delimiter, |_| Ok(()))?;
+    loop {
+        if scanner.starts_with(b"--")? {
+            // close delimiter, whatever follows is epilogue
+            while scanner.fill()? {
+                scanner.buffer.clear();
+            }
+            return Ok(parts);
+        }
+        // transport padding up to the end of the delimiter line
+        if !scanner
+            .read_line()?
+            .iter()
+            .all(|byte| *byte == b' ' || *byte == b'\t')
+        {
+            return Err(MultipartError::Malformed);
+        }
+
+        let headers = read_part_headers(&mut scanner)?;
+        let disposition = headers
+            .get("Content-Disposition")
+            .ok_or(MultipartError::Malformed)?;
+        let (name, filename) = parse_disposition(disposition)?;
+        let content_type = headers.get("Content-Type").map(str::to_string);
+
+        // only file parts are worth spooling, text fields end up in the form map
+        let spool = filename.is_some();
+        let mut sink = Sink::Memory(Vec::new());
+        scanner.read_until(&delimiter, |data| {
+            if sink.size() + data.len() as u64 > limits.max_part_size {
+                return Err(MultipartError::TooLarge);
+            }
+            sink.write(data, spool, limits)
+        })?;
+
+        parts.push(Part {
+            name,
+            filename,
+            content_type,
+            headers,
+            data: sink.finish()?,
+        });
+    }
+}
+
+fn read_part_headers<R: Read>(scanner: &mut Scanner<R>) -> Result<HeaderMap, MultipartError> {
+    let mut headers = HeaderMap::new();
+    loop {
+        let line = scanner.read_line()?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        if headers.len() == MAX_PART_HEADERS {
+            return Err(MultipartError::Malformed);
+        }
+        let line = String::from_utf8(line).map_err(|_| MultipartError::Malformed)?;
+        let (header, value) = headers::split_header(&line).ok_or(MultipartError::Malformed)?;
+        headers.append(header, value);
+    }
+}
+
+// `form-data; name="field"; filename="photo.jpg"`
+fn parse_disposition(disposition: &str) -> Result<(String, Option<String>), MultipartError> {
+    let (kind, mut rest) = disposition.split_once(';').unwrap_or((disposition, ""));
+    if !kind.trim().eq_ignore_ascii_case("form-data") {
+        return Err(MultipartError::Malformed);
+    }
+
+    let mut name = None;
+    let mut filename = None;
+    while !rest.trim().is_empty() {
+        let (param, value) = rest.split_once('=').ok_or(MultipartError::Malformed)?;
+        let value = value.trim_start();
+        let (value, remaining) = match value.strip_prefix('"') {
+            Some(quoted) => parse_quoted(quoted)?,
+            None => {
+                let (value, remaining) = value.split_once(';').unwrap_or((value, ""));
+                (value.trim().to_string(), remaining)
+            }
+        };
+        match param.trim().to_lowercase().as_str() {
+            "name" => name = Some(value),
+            "filename" => filename = Some(value),
+            _ => (),
+        }
+        rest = remaining;
+    }
+
+    match name {
+        Some(name) => Ok((name, filename)),
+        None => Err(MultipartError::Malformed),
+    }
+}
+
+// returns the unescaped string and whatever follows the next `;`
+fn parse_quoted(input: &str) -> Result<(String, &str), MultipartError> {
+    let mut value = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, char)) = chars.next() {
+        match char {
+            '\\' => match chars.next() {
+                Some((_, escaped)) => value.push(escaped),
+                None => return Err(MultipartError::Malformed),
+            },
+            '"' => {
+                let remaining = input[i + 1..].trim_start();
+                return match remaining.strip_prefix(';') {
+                    Some(remaining) => Ok((value, remaining)),
+                    None if remaining.is_empty() => Ok((value, remaining)),
+                    None => Err(MultipartError::Malformed),
+                };
+            }
+            char => value.push(char),
+        }
+    }
+    Err(MultipartError::Malformed)
+}
+
+struct Scanner<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    read_total: u64,
+    max_total: u64,
+}
+
+impl<R: Read> Scanner<R> {
+    fn fill(&mut self) -> Result<bool, MultipartError> {
+        let mut chunk = [0; READ_SIZE];
+        loop {
+            return match self.reader.read(&mut chunk) {
+                Ok(0) => Ok(false),
+                Ok(read) => {
+                    self.read_total += read as u64;
+                    if self.read_total > self.max_total {
+                        return Err(MultipartError::TooLarge);
+                    }
+                    self.buffer.extend_from_slice(&chunk[..read]);
+                    Ok(true)
+                }
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) if request::is_timeout(&error) => Err(MultipartError::TimedOut),
+                Err(_) => Err(MultipartError::Malformed),
+            };
+        }
+    }
+
+    fn starts_with(&mut self, prefix: &[u8]) -> Result<bool, MultipartError> {
+        while self.buffer.len() < prefix.len() {
+            if !self.fill()? {
+                return Err(MultipartError::Malformed);
+            }
+        }
+        Ok(self.buffer.starts_with(prefix))
+    }
+
+    // CRLF-terminated line without the line break
+    fn read_line(&mut self) -> Result<Vec<u8>, MultipartError> {
+        let mut searched = 0;
+        loop {
+            if let Some(position) = find(&self.buffer[searched..], b"\r\n") {
+                let end = searched + position;
+                let line = self.buffer[..end].to_vec();
+                self.buffer.drain(..end + 2);
+                return Ok(line);
+            }
+            if self.buffer.len() > MAX_HEADER_LINE {
+                return Err(MultipartError::Malformed);
+            }
+            searched = self.buffer.len().saturating_sub(1);
+            if !self.fill()? {
+                return Err(MultipartError::Malformed);
+            }
+        }
+    }
+
+    // hands everything up to `delimiter` to `sink` and consumes the delimiter,
+    // keeping back just enough bytes to spot a delimiter split across reads
+    fn read_until<F>(&mut self, delimiter: &[u8], mut sink: F) -> Result<(), MultipartError>
+    where
+        F: FnMut(&[u8]) -> Result<(), MultipartError>,
+    {
+        loop {
+            if let Some(position) = find(&self.buffer, delimiter) {
+                sink(&self.buffer[..position])?;
+                self.buffer.drain(..position + delimiter.len());
+                return Ok(());
+            }
+
+            let keep = delimiter.len() - 1;
+            if self.buffer.len() > keep {
+                let emit = self.buffer.len() - keep;
+                sink(&self.buffer[..emit])?;
+                self.buffer.drain(..emit);
+            }
+            if !self.fill()? {
+                return Err(MultipartError::Malformed);
+            }
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    mod boundary {
+        use super::super::*;
+
+        #[test]
+        fn plain_and_quoted() {
+            assert_eq!(
+                boundary("multipart/form-data; boundary=abc123").as_deref(),
+                Some("abc123")
+            );
+            assert_eq!(
+                boundary("Multipart/Form-Data; charset=utf-8; boundary=\"a b\"").as_deref(),
+                Some("a b")
+            );
+        }
+
+        #[test]
+        fn missing_or_wrong_type() {
+            assert_eq!(boundary("multipart/form-data"), None);
+            assert_eq!(boundary("multipart/mixed; boundary=abc"), None);
+            assert_eq!(boundary("multipart/form-data; boundary="), None);
+        }
+    }
+
+    mod parse_multipart {
+        use super::super::*;
+
+        const BODY: &str = "preamble\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\
+            \r\n\
+            Holiday\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"photo\"; filename=\"beach \\\"1\\\".png\"\r\n\
+            Content-Type: image/png\r\n\
+            \r\n\
+            \u{1}PNG\r\n--Xy\r\n\
+            --XyZ--\r\n\
+            epilogue";
+
+        // hands out at most `size` bytes per read to exercise the buffering
+        struct Trickle<'a>(&'a [u8], usize);
+
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let size = self.1.min(buf.len()).min(self.0.len());
+                buf[..size].copy_from_slice(&self.0[..size]);
+                self.0 = &self.0[size..];
+                Ok(size)
+            }
+        }
+
+        #[test]
+        fn text_and_file_parts() {
+            for read_size in [1, 3, 7, 4096] {
+                let reader = Trickle(BODY.as_bytes(), read_size);
+                let parts = parse_multipart(reader, "XyZ", &MultipartLimits::default()).unwrap();
+
+                assert_eq!(parts.len(), 2);
+                assert_eq!(parts[0].name, "title");
+                assert_eq!(parts[0].filename, None);
+                assert_eq!(parts[0].bytes().unwrap(), b"Holiday");
+
+                assert_eq!(parts[1].name, "photo");
+                assert_eq!(parts[1].filename.as_deref(), Some("beach \"1\".png"));
+                assert_eq!(parts[1].content_type.as_deref(), Some("image/png"));
+                assert_eq!(parts[1].bytes().unwrap(), b"\x01PNG\r\n--Xy");
+            }
+        }
+
+        #[test]
+        fn spools_large_files() {
+            let limits = MultipartLimits {
+                spool_threshold: Some(4),
+                ..MultipartLimits::default()
+            };
+            let parts = parse_multipart(BODY.as_bytes(), "XyZ", &limits).unwrap();
+
+            assert!(matches!(parts[0].data, PartData::Memory(_)));
+            let path = match &parts[1].data {
+                PartData::File(file) => file.path().to_path_buf(),
+                PartData::Memory(_) => panic!("expected the file part to be spooled"),
+            };
+            assert_eq!(fs::read(&path).unwrap(), b"\x01PNG\r\n--Xy");
+            assert_eq!(parts[1].size(), 10);
+
+            drop(parts);
+            assert!(!path.exists());
+        }
+
+        #[test]
+        fn enforces_limits() {
+            let limits = MultipartLimits {
+                max_part_size: 8,
+                ..MultipartLimits::default()
+            };
+            assert!(matches!(
+                parse_multipart(BODY.as_bytes(), "XyZ", &limits),
+                Err(MultipartError::TooLarge)
+            ));
+
+            let limits = MultipartLimits {
+                max_total_size: 32,
+                ..MultipartLimits::default()
+            };
+            assert!(matches!(
+                parse_multipart(BODY.as_bytes(), "XyZ", &limits),
+                Err(MultipartError::TooLarge)
+            ));
+        }
+
+        #[test]
+        fn rejects_malformed_bodies() {
+            let limits = MultipartLimits::default();
+            for body in [
+                "--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nno close",
+                "--XyZ\r\nContent-Type: text/plain\r\n\r\nx\r\n--XyZ--",
+                "--XyZ\r\nContent-Disposition: attachment; name=\"a\"\r\n\r\nx\r\n--XyZ--",
+                "no delimiter at all",
+            ] {
+                assert!(matches!(
+                    parse_multipart(body.as_bytes(), "XyZ", &limits),
+                    Err(MultipartError::Malformed)
+                ));
+            }
+        }
+    }
+}
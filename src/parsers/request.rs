@@ -1,96 +1,510 @@
 use std::{
     collections::HashMap,
-    io::{BufReader, Read},
-    net::TcpStream,
+    io::{self, BufRead, Read},
+    sync::OnceLock,
 };
 
 use super::{
-    json::{parse_json, JsonValue},
+    headers::{self, HeaderMap},
+    json::{parse_json, parse_json_with, JsonError, JsonValue, ParseOptions},
+    multipart::{self, MultipartError, MultipartLimits, Part},
     response::{self, HttpResponse},
+    urlencoded,
 };
 
 pub struct HttpRequest {
     pub method: String,
     pub uri: String,
-    pub headers: HashMap<String, String>,
-    pub body: Option<JsonValue>,
+    pub version: String,
+    pub path: String,
+    pub headers: HeaderMap,
+    pub(crate) body: Vec<u8>,
+    pub(crate) json: OnceLock<Result<JsonValue, JsonError>>,
+    pub(crate) form: HashMap<String, Vec<String>>,
+    pub(crate) files: Vec<Part>,
+    pub(crate) query: HashMap<String, Vec<String>>,
+    pub(crate) params: HashMap<String, String>,
 }
 
-pub fn split_request(stream: &TcpStream) -> Option<(Vec<String>, String)> {
-    let buf_reader = BufReader::new(stream);
+impl HttpRequest {
+    pub fn params(&self) -> &HashMap<String, String> {
+        &self.params
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    // HTTP/1.1 connections stay open unless told otherwise, HTTP/1.0 ones
+    // only when the client explicitly asks for it (RFC 9112 section 9.3)
+    pub fn keep_alive(&self) -> bool {
+        let has_option = |option: &str| {
+            self.header("Connection").is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|token| token.trim().eq_ignore_ascii_case(option))
+            })
+        };
+        match self.version.as_str() {
+            "HTTP/1.0" => has_option("keep-alive"),
+            _ => !has_option("close"),
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.body).ok()
+    }
+
+    // lowercased type/subtype of Content-Type, without parameters
+    pub fn media_type(&self) -> Option<String> {
+        let content_type = self.header("Content-Type")?;
+        let media_type = content_type.split(';').next().unwrap_or_default();
+        Some(media_type.trim().to_lowercase())
+    }
+
+    // JSON bodies are decoded on first use and kept for later calls,
+    // anything else asking to be read as JSON is the wrong media type
+    pub fn json(&self) -> Result<&JsonValue, HttpResponse> {
+        let text = self.json_text()?;
+        match self.json.get_or_init(|| parse_json(text)) {
+            Ok(value) => Ok(value),
+            Err(error) => Err(invalid_json(error.clone())),
+        }
+    }
+
+    // decodes the body with other options than the defaults, e.g. raw
+    // numbers for exact amounts that would not survive an f64
+    pub fn json_with(&self, options: &ParseOptions) -> Result<JsonValue, HttpResponse> {
+        parse_json_with(self.json_text()?, options).map_err(invalid_json)
+    }
+
+    fn json_text(&self) -> Result<&str, HttpResponse> {
+        if !self
+            .media_type()
+            .is_some_and(|media_type| is_json(&media_type))
+        {
+            return Err(response::http_error(
+                response::HttpCode::UnsupportedMediaType,
+                "expected an application/json body",
+            ));
+        }
+        if self.body.is_empty() {
+            return Err(response::http_error(
+                response::HttpCode::BadRequest,
+                "request body is empty",
+            ));
+        }
+        match self.text() {
+            Some(text) => Ok(text),
+            None => Err(response::http_error(
+                response::HttpCode::BadRequest,
+                "request body is not valid UTF-8",
+            )),
+        }
+    }
+
+    pub fn form(&self, name: &str) -> Option<&str> {
+        self.form_all(name).first().map(String::as_str)
+    }
+
+    pub fn form_all(&self, name: &str) -> &[String] {
+        match self.form.get(name) {
+            Some(values) => values,
+            None => &[],
+        }
+    }
+
+    // file parts of a multipart body, text fields are part of the form
+    pub fn file(&self, name: &str) -> Option<&Part> {
+        self.files.iter().find(|part| part.name == name)
+    }
 
+    pub fn files(&self) -> &[Part] {
+        &self.files
+    }
+
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query_all(name).first().map(String::as_str)
+    }
+
+    pub fn query_all(&self, name: &str) -> &[String] {
+        match self.query.get(name) {
+            Some(values) => values,
+            None => &[],
+        }
+    }
+}
+
+fn is_json(media_type: &str) -> bool {
+    media_type == "application/json" || media_type.ends_with("+json")
+}
+
+fn invalid_json(error: JsonError) -> HttpResponse {
+    response::http_error(
+        response::HttpCode::BadRequest,
+        &format!(
+            "request body is not valid JSON: {error} (byte {})",
+            error.offset
+        ),
+    )
+}
+
+pub enum ReadError {
+    // the peer went away or stayed idle before starting another request
+    Closed,
+    Malformed,
+    // a transfer coding other than chunked, answered with 501
+    Unsupported,
+    // request line and header fields over `max_header_bytes`, answered with 431
+    HeadersTooLarge,
+    TooLarge,
+    // the request didn't arrive in time, answered with 408
+    TimedOut,
+    // the body could not be stored on our side, answered with 500
+    Storage(io::Error),
+}
+
+const MAX_CHUNK_LINE: usize = 1024;
+
+#[derive(Clone, Debug)]
+pub struct RequestLimits {
+    // request line, header fields and chunked trailers together
+    pub max_header_bytes: usize,
+    // bodies buffered in memory, multipart bodies are streamed and bounded
+    // by their own limits instead
+    pub max_body_bytes: u64,
+    pub multipart: MultipartLimits,
+}
+
+impl Default for RequestLimits {
+    fn default() -> RequestLimits {
+        RequestLimits {
+            max_header_bytes: 16 * 1024,
+            max_body_bytes: 1024 * 1024,
+            multipart: MultipartLimits::default(),
+        }
+    }
+}
+
+// multipart bodies are parsed while they're read off the connection so
+// large uploads never have to sit in memory, everything else is buffered
+pub enum RequestBody {
+    Bytes(Vec<u8>),
+    Multipart(Vec<Part>),
+}
+
+// request line and header fields, read apart from the body so the caller
+// can time the two differently
+pub struct RequestHead {
+    lines: Vec<String>,
+    // what's left of the header budget for chunked trailers
+    trailer_budget: usize,
+}
+
+pub fn read_head<R: BufRead>(
+    reader: &mut R,
+    limits: &RequestLimits,
+) -> Result<RequestHead, ReadError> {
     let mut request_lines: Vec<String> = Vec::new();
-    let mut char_sequence: Vec<u8> = Vec::new();
-    let mut body_sequence: Vec<u8> = Vec::new();
-    let mut content_length: usize = 0;
-    let mut bytes = buf_reader.bytes().into_iter();
-
-    while let Some(byte) = bytes.next() {
-        let last_request_line = request_lines
-            .last()
-            .unwrap_or(&String::from("--"))
-            .trim()
-            .to_lowercase();
-
-        match &last_request_line {
-            line if line.starts_with("content-length") => {
-                content_length = match line.split_once(":") {
-                    Some((_, length)) => match length.trim().parse() {
-                        Ok(v @ 1..) => v,
-                        Ok(0) => return Some((request_lines, String::from(""))),
-                        Err(_) => return None,
-                    },
-                    None => return None,
-                };
+    let mut header_budget = limits.max_header_bytes;
+
+    loop {
+        let value = match read_line(reader, &mut header_budget) {
+            Ok(value) => value,
+            Err(ReadError::Closed) if !request_lines.is_empty() => {
+                return Err(ReadError::Malformed)
             }
-            _ => (),
+            Err(error) => return Err(error),
+        };
+        match value {
+            // stray blank lines ahead of a request line are allowed (RFC 9112 section 2.2)
+            value if value.is_empty() && request_lines.is_empty() => continue,
+            value if value.is_empty() => break,
+            value => request_lines.push(value),
         }
+    }
 
-        match byte {
-            Ok(char) if char as char == '\n' => match String::from_utf8(char_sequence.clone()) {
-                Ok(value) if value.trim().is_empty() => break,
-                Ok(value) => {
-                    dbg!("{:#?}", &value);
-                    request_lines.push(value);
-                    char_sequence.clear()
+    // a field line that doesn't parse could still mean something to the
+    // next hop, so it is refused instead of skipped
+    if request_lines
+        .iter()
+        .skip(1)
+        .any(|line| headers::split_header(line).is_none())
+    {
+        return Err(ReadError::Malformed);
+    }
+
+    Ok(RequestHead {
+        lines: request_lines,
+        trailer_budget: header_budget,
+    })
+}
+
+// the body as framed by the head, chunked trailers end up with the header
+// lines
+pub fn read_body<R: BufRead>(
+    reader: &mut R,
+    head: RequestHead,
+    limits: &RequestLimits,
+) -> Result<(Vec<String>, RequestBody), ReadError> {
+    let mut request_lines = head.lines;
+    let transfer_codings: Vec<String> = header_values(&request_lines, "transfer-encoding")
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(|coding| coding.trim().to_lowercase())
+        .collect();
+    let content_lengths: Vec<&str> = header_values(&request_lines, "content-length")
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    let boundary = header_values(&request_lines, "content-type")
+        .first()
+        .and_then(|content_type| multipart::boundary(content_type));
+
+    let body = match (transfer_codings.last(), content_lengths.first()) {
+        (None, None) => RequestBody::Bytes(Vec::new()),
+        // both framings at once is the classic request smuggling setup, the
+        // message length can't be trusted so the request is refused outright
+        // (RFC 9112 section 6.3)
+        (Some(_), Some(_)) => return Err(ReadError::Malformed),
+        (Some(last), None) if last != "chunked" => return Err(ReadError::Malformed),
+        (Some(_), None) if transfer_codings.len() > 1 => return Err(ReadError::Unsupported),
+        (Some(_), None) => {
+            let mut chunked = ChunkedReader {
+                reader: &mut *reader,
+                remaining: 0,
+                started: false,
+                finished: false,
+                trailers: Vec::new(),
+                trailer_budget: head.trailer_budget,
+                error: None,
+            };
+            let body = read_content(&mut chunked, boundary, limits);
+            // a multipart body may end before the last chunk was read
+            let drained = body.is_ok()
+                && (chunked.finished || io::copy(&mut chunked, &mut io::sink()).is_ok());
+            // the chunked framing knows best what went wrong underneath
+            let body = match (chunked.error.take(), body) {
+                (Some(error), _) => return Err(error),
+                (None, Err(error)) => return Err(error),
+                (None, Ok(_)) if !drained => return Err(ReadError::Malformed),
+                (None, Ok(body)) => body,
+            };
+            request_lines.append(&mut chunked.trailers);
+            body
+        }
+        (None, Some(length)) => {
+            // `u64::from_str` would also take a leading `+`, the grammar is
+            // just `1*DIGIT` (RFC 9110 section 8.6)
+            if content_lengths.iter().any(|other| other != length)
+                || length.is_empty()
+                || !length.bytes().all(|byte| byte.is_ascii_digit())
+            {
+                return Err(ReadError::Malformed);
+            }
+            let content_length: u64 = match length.parse() {
+                Ok(length) => length,
+                Err(_) => return Err(ReadError::Malformed),
+            };
+            // refused before reading any of it
+            if boundary.is_none() && content_length > limits.max_body_bytes {
+                return Err(ReadError::TooLarge);
+            }
+            let mut limited = (&mut *reader).take(content_length);
+            let body = read_content(&mut limited, boundary, limits)?;
+            if limited.limit() > 0 {
+                return Err(ReadError::Malformed);
+            }
+            body
+        }
+    };
+
+    Ok((request_lines, body))
+}
+
+fn read_content<R: Read>(
+    reader: &mut R,
+    boundary: Option<String>,
+    limits: &RequestLimits,
+) -> Result<RequestBody, ReadError> {
+    let boundary = match boundary {
+        Some(boundary) => boundary,
+        None => {
+            let mut body_sequence = Vec::new();
+            match reader
+                .take(limits.max_body_bytes + 1)
+                .read_to_end(&mut body_sequence)
+            {
+                Ok(_) if body_sequence.len() as u64 > limits.max_body_bytes => {
+                    return Err(ReadError::TooLarge)
                 }
-                Err(_) => return None,
-            },
-            Ok(char) => char_sequence.push(char),
-            Err(_) => return None,
+                Ok(_) => return Ok(RequestBody::Bytes(body_sequence)),
+                Err(error) if is_timeout(&error) => return Err(ReadError::TimedOut),
+                Err(_) => return Err(ReadError::Malformed),
+            }
+        }
+    };
+
+    match multipart::parse_multipart(reader, &boundary, &limits.multipart) {
+        Ok(parts) => Ok(RequestBody::Multipart(parts)),
+        Err(MultipartError::Malformed) => Err(ReadError::Malformed),
+        Err(MultipartError::TooLarge) => Err(ReadError::TooLarge),
+        Err(MultipartError::TimedOut) => Err(ReadError::TimedOut),
+        Err(MultipartError::Storage(error)) => Err(ReadError::Storage(error)),
+    }
+}
+
+// chunk-size [ ; extensions ] CRLF data CRLF ... 0 CRLF trailers CRLF,
+// trailer fields are collected unless they'd change framing
+struct ChunkedReader<'a, R> {
+    reader: &'a mut R,
+    remaining: usize,
+    started: bool,
+    finished: bool,
+    trailers: Vec<String>,
+    // what's left of the header budget
+    trailer_budget: usize,
+    // `Read` can only report an io::Error, the real cause is kept here
+    error: Option<ReadError>,
+}
+
+impl<R: BufRead> ChunkedReader<'_, R> {
+    fn next_chunk(&mut self) -> Result<(), ReadError> {
+        let malformed = |error| match error {
+            ReadError::TimedOut => ReadError::TimedOut,
+            _ => ReadError::Malformed,
         };
+
+        if self.started
+            && !read_line(self.reader, &mut MAX_CHUNK_LINE.clone())
+                .map_err(malformed)?
+                .is_empty()
+        {
+            return Err(ReadError::Malformed);
+        }
+        self.started = true;
+
+        let line = read_line(self.reader, &mut MAX_CHUNK_LINE.clone()).map_err(malformed)?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        if size.is_empty() || !size.chars().all(|char| char.is_ascii_hexdigit()) {
+            return Err(ReadError::Malformed);
+        }
+        self.remaining = match usize::from_str_radix(size, 16) {
+            Ok(size) => size,
+            Err(_) => return Err(ReadError::Malformed),
+        };
+        if self.remaining > 0 {
+            return Ok(());
+        }
+
+        loop {
+            let line = match read_line(self.reader, &mut self.trailer_budget) {
+                Ok(line) => line,
+                Err(ReadError::HeadersTooLarge) => return Err(ReadError::HeadersTooLarge),
+                Err(error) => return Err(malformed(error)),
+            };
+            if line.is_empty() {
+                break;
+            }
+            let header = headers::split_header(&line).map(|(header, _)| header);
+            match header {
+                Some(header)
+                    if ["content-length", "transfer-encoding", "trailer", "host"]
+                        .iter()
+                        .any(|framing| header.eq_ignore_ascii_case(framing)) => {}
+                Some(_) => self.trailers.push(line),
+                None => return Err(ReadError::Malformed),
+            }
+        }
+        self.finished = true;
+        Ok(())
     }
+}
 
-    if content_length > 0 {
-        for byte in bytes {
-            match content_length {
-                0 => (),
-                1.. => match byte {
-                    Ok(char) => {
-                        body_sequence.push(char);
-                        if body_sequence.len() == content_length {
-                            break;
-                        } else {
-                            continue;
-                        }
-                    }
-                    Err(_) => continue,
-                },
+impl<R: BufRead> Read for ChunkedReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 && !self.finished {
+            if let Err(error) = self.next_chunk() {
+                self.error = Some(error);
+                return Err(io::ErrorKind::InvalidData.into());
             }
         }
+        if self.finished || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let limit = buf.len().min(self.remaining);
+        let read = match self.reader.read(&mut buf[..limit]) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(read) => read,
+            Err(error) => {
+                if is_timeout(&error) {
+                    self.error = Some(ReadError::TimedOut);
+                }
+                return Err(error);
+            }
+        };
+        self.remaining -= read;
+        Ok(read)
     }
+}
+
+// sockets report an expired read timeout as WouldBlock on some platforms
+pub(crate) fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    )
+}
 
-    let body_str = match String::from_utf8(body_sequence) {
-        Ok(v) => v,
-        Err(_) => String::from(""),
+// reads at most `budget` bytes and takes what the line used off it, line
+// ending included, a line that doesn't end by then is too large
+fn read_line<R: BufRead>(reader: &mut R, budget: &mut usize) -> Result<String, ReadError> {
+    let limit = *budget;
+    if limit == 0 {
+        return Err(ReadError::HeadersTooLarge);
+    }
+
+    let mut line: Vec<u8> = Vec::new();
+    match (&mut *reader)
+        .take(limit as u64)
+        .read_until(b'\n', &mut line)
+    {
+        Err(error) if is_timeout(&error) => return Err(ReadError::TimedOut),
+        Ok(0) | Err(_) if line.is_empty() => return Err(ReadError::Closed),
+        Ok(_) if line.ends_with(b"\n") => (),
+        Ok(_) if line.len() == limit => return Err(ReadError::HeadersTooLarge),
+        _ => return Err(ReadError::Malformed),
+    }
+    *budget = match limit.checked_sub(line.len()) {
+        Some(left) => left,
+        None => return Err(ReadError::HeadersTooLarge),
     };
 
-    Some((request_lines, body_str))
+    match String::from_utf8(line) {
+        Ok(value) => Ok(value.trim_end_matches(['\r', '\n']).to_string()),
+        Err(_) => Err(ReadError::Malformed),
+    }
+}
+
+fn header_values<'a>(request_lines: &'a [String], name: &str) -> Vec<&'a str> {
+    request_lines
+        .iter()
+        .skip(1)
+        .filter_map(|line| headers::split_header(line))
+        .filter(|(header, _)| header.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value)
+        .collect()
 }
 
 pub fn parse_request(
     http_request_lines: Vec<String>,
-    body: String,
+    body: RequestBody,
 ) -> Result<HttpRequest, HttpResponse> {
     let parse_error =
         response::http_error(response::HttpCode::BadRequest, "could not parse request");
@@ -104,42 +518,712 @@ pub fn parse_request(
     let mut request_line_iter = request_line.split_whitespace();
 
     let method = match request_line_iter.next() {
-        Some(method @ ("GET" | "POST" | "PATCH" | "DELETE")) => method,
+        Some(
+            method @ ("GET" | "HEAD" | "POST" | "PUT" | "PATCH" | "DELETE" | "OPTIONS" | "TRACE"
+            | "CONNECT"),
+        ) => method,
         _ => return Err(parse_error),
     };
 
     let uri = request_line_iter.next();
 
-    let header_vector: Vec<(String, String)> = http_request_line_iter
-        .filter_map(|request_line| {
-            let header_values: Vec<&str> = match request_line.find(":") {
-                Some(_) => request_line.split(":").collect(),
-                None => return None,
+    let version = match request_line_iter.next() {
+        Some(version @ ("HTTP/1.0" | "HTTP/1.1")) => version,
+        Some(version) if version.starts_with("HTTP/") => {
+            return Err(response::http_error(
+                response::HttpCode::HttpVersionNotSupported,
+                "only HTTP/1.0 and HTTP/1.1 are supported",
+            ))
+        }
+        _ => return Err(parse_error),
+    };
+
+    // `read_head` already refused malformed field lines
+    let headers: HeaderMap = http_request_line_iter
+        .filter_map(|request_line| headers::split_header(request_line))
+        .map(|(header, value)| (header.to_string(), value.to_string()))
+        .collect();
+
+    let uri = match uri {
+        Some(uri) => uri,
+        None => return Err(parse_error),
+    };
+    let (raw_path, raw_query) = uri.split_once('?').unwrap_or((uri, ""));
+
+    let path = match urlencoded::percent_decode(raw_path, false) {
+        Some(path) => path,
+        None => {
+            return Err(response::http_error(
+                response::HttpCode::BadRequest,
+                "could not decode path",
+            ))
+        }
+    };
+    let query = match urlencoded::parse_urlencoded(raw_query) {
+        Some(query) => query,
+        None => {
+            return Err(response::http_error(
+                response::HttpCode::BadRequest,
+                "could not decode query string",
+            ))
+        }
+    };
+
+    let (body, parts) = match body {
+        RequestBody::Bytes(body) => (body, Vec::new()),
+        RequestBody::Multipart(parts) => (Vec::new(), parts),
+    };
+
+    let mut request = HttpRequest {
+        method: method.to_string(),
+        uri: uri.to_string(),
+        version: version.to_string(),
+        path,
+        headers,
+        body,
+        json: OnceLock::new(),
+        form: HashMap::new(),
+        files: Vec::new(),
+        query,
+        params: HashMap::new(),
+    };
+
+    // JSON is decoded by the handler, with the options it asks for, and
+    // bodies of any other media type are left raw
+    match request.media_type() {
+        Some(media_type) if media_type == "application/x-www-form-urlencoded" => {
+            let form = request.text().and_then(urlencoded::parse_urlencoded);
+            request.form = match form {
+                Some(form) => form,
+                None => {
+                    return Err(response::http_error(
+                        response::HttpCode::BadRequest,
+                        "could not decode form body",
+                    ))
+                }
             };
-            let header = header_values.get(0);
-            let value = header_values.get(1);
-            match (header, value) {
-                (Some(header), Some(value)) => {
-                    Some((header.trim().to_string(), value.trim().to_string()))
+        }
+        Some(media_type) if media_type == "multipart/form-data" => {
+            // without a usable boundary `read_body` left the body raw
+            if !request.body.is_empty() {
+                return Err(response::http_error(
+                    response::HttpCode::BadRequest,
+                    "multipart body is missing a valid boundary",
+                ));
+            }
+            for part in parts {
+                if part.filename.is_some() {
+                    request.files.push(part);
+                    continue;
                 }
-                _ => None,
+                let value = match part
+                    .bytes()
+                    .ok()
+                    .and_then(|bytes| String::from_utf8(bytes).ok())
+                {
+                    Some(value) => value,
+                    None => {
+                        return Err(response::http_error(
+                            response::HttpCode::BadRequest,
+                            "form field is not valid UTF-8",
+                        ))
+                    }
+                };
+                request.form.entry(part.name).or_default().push(value);
             }
-        })
-        .collect();
+        }
+        _ => (),
+    }
+
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    mod split_request {
+        use super::super::*;
+        use std::io::Cursor;
+
+        fn split_request<R: BufRead>(
+            reader: &mut R,
+            limits: &RequestLimits,
+        ) -> Result<(Vec<String>, RequestBody), ReadError> {
+            let head = read_head(reader, limits)?;
+            read_body(reader, head, limits)
+        }
+
+        fn bytes(body: RequestBody) -> Vec<u8> {
+            match body {
+                RequestBody::Bytes(bytes) => bytes,
+                RequestBody::Multipart(_) => panic!("expected a raw body"),
+            }
+        }
+
+        #[test]
+        fn reads_headers_and_body() {
+            let mut reader = Cursor::new(
+                "POST / HTTP/1.1\r\nHost: localhost:8000\r\ncontent-length: 4\r\n\r\ntest",
+            );
+            let (lines, body) = split_request(&mut reader, &RequestLimits::default())
+                .ok()
+                .unwrap();
+            assert_eq!(
+                lines,
+                vec![
+                    "POST / HTTP/1.1",
+                    "Host: localhost:8000",
+                    "content-length: 4"
+                ]
+            );
+            assert_eq!(bytes(body), b"test");
+        }
+
+        #[test]
+        fn reads_pipelined_requests() {
+            let mut reader = Cursor::new(
+                "POST /a HTTP/1.1\r\nContent-Length: 2\r\n\r\nhiGET /b HTTP/1.1\r\n\r\n",
+            );
+            let (lines, body) = split_request(&mut reader, &RequestLimits::default())
+                .ok()
+                .unwrap();
+            assert_eq!(lines[0], "POST /a HTTP/1.1");
+            assert_eq!(bytes(body), b"hi");
+
+            let (lines, body) = split_request(&mut reader, &RequestLimits::default())
+                .ok()
+                .unwrap();
+            assert_eq!(lines, vec!["GET /b HTTP/1.1"]);
+            assert!(bytes(body).is_empty());
+
+            assert!(matches!(
+                split_request(&mut reader, &RequestLimits::default()),
+                Err(ReadError::Closed)
+            ));
+        }
+
+        #[test]
+        fn truncated_request() {
+            let mut reader = Cursor::new("GET / HTTP/1.1\r\nHost: x");
+            assert!(matches!(
+                split_request(&mut reader, &RequestLimits::default()),
+                Err(ReadError::Malformed)
+            ));
+
+            let mut reader = Cursor::new("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort");
+            assert!(matches!(
+                split_request(&mut reader, &RequestLimits::default()),
+                Err(ReadError::Malformed)
+            ));
+        }
+
+        #[test]
+        fn decodes_chunked_body() {
+            let mut reader = Cursor::new(
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                 4\r\nWiki\r\n7;name=value\r\npedia i\r\nB\r\nn \r\nchunks.\r\n0\r\n\
+                 Expires: never\r\nContent-Length: 99\r\n\r\nGET / HTTP/1.1\r\n\r\n",
+            );
+            let (lines, body) = split_request(&mut reader, &RequestLimits::default())
+                .ok()
+                .unwrap();
+            assert_eq!(bytes(body), b"Wikipedia in \r\nchunks.");
+            assert_eq!(
+                lines,
+                vec![
+                    "POST / HTTP/1.1",
+                    "Transfer-Encoding: chunked",
+                    "Expires: never"
+                ]
+            );
+
+            let (lines, _) = split_request(&mut reader, &RequestLimits::default())
+                .ok()
+                .unwrap();
+            assert_eq!(lines, vec!["GET / HTTP/1.1"]);
+        }
+
+        #[test]
+        fn rejects_bad_chunks() {
+            for request in [
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabcd\r\n0\r\n\r\n",
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n",
+            ] {
+                assert!(matches!(
+                    split_request(&mut Cursor::new(request), &RequestLimits::default()),
+                    Err(ReadError::Malformed)
+                ));
+            }
+        }
+
+        #[test]
+        fn rejects_ambiguous_framing() {
+            let mut reader = Cursor::new(
+                "POST / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+            );
+            assert!(matches!(
+                split_request(&mut reader, &RequestLimits::default()),
+                Err(ReadError::Malformed)
+            ));
+
+            let mut reader =
+                Cursor::new("POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nab");
+            assert!(matches!(
+                split_request(&mut reader, &RequestLimits::default()),
+                Err(ReadError::Malformed)
+            ));
+
+            for request in [
+                "POST / HTTP/1.1\r\nTransfer-Encoding : chunked\r\n\r\n0\r\n\r\n",
+                "POST / HTTP/1.1\r\nHost: x\r\n Transfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+                "POST / HTTP/1.1\r\nHost: x\r\n\tTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+                "POST / HTTP/1.1\r\nContent-Length: +2\r\n\r\nab",
+                "POST / HTTP/1.1\r\nContent-Length: -0\r\n\r\n",
+                "POST / HTTP/1.1\r\nContent-Length: \r\n\r\n",
+                "POST / HTTP/1.1\r\nno colon here\r\n\r\n",
+            ] {
+                let mut reader = Cursor::new(request);
+                assert!(
+                    matches!(
+                        split_request(&mut reader, &RequestLimits::default()),
+                        Err(ReadError::Malformed)
+                    ),
+                    "accepted {request:?}"
+                );
+            }
+        }
+
+        #[test]
+        fn transfer_codings() {
+            let mut reader = Cursor::new("POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n");
+            assert!(matches!(
+                split_request(&mut reader, &RequestLimits::default()),
+                Err(ReadError::Malformed)
+            ));
+
+            let mut reader =
+                Cursor::new("POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n");
+            assert!(matches!(
+                split_request(&mut reader, &RequestLimits::default()),
+                Err(ReadError::Unsupported)
+            ));
+        }
+        #[test]
+        fn streams_multipart_body() {
+            let mut reader = Cursor::new(
+                "POST / HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b\r\n\
+                 Transfer-Encoding: chunked\r\n\r\n\
+                 1a\r\n--b\r\nContent-Disposition: \r\n\
+                 23\r\nform-data; name=\"a\"\r\n\r\none\r\n--b--\r\n\r\n\
+                 0\r\n\r\nGET / HTTP/1.1\r\n\r\n",
+            );
+            let (_, body) = split_request(&mut reader, &RequestLimits::default())
+                .ok()
+                .unwrap();
+            match body {
+                RequestBody::Multipart(parts) => {
+                    assert_eq!(parts.len(), 1);
+                    assert_eq!(parts[0].name, "a");
+                    assert_eq!(parts[0].bytes().unwrap(), b"one");
+                }
+                RequestBody::Bytes(_) => panic!("expected a multipart body"),
+            }
+
+            let (lines, _) = split_request(&mut reader, &RequestLimits::default())
+                .ok()
+                .unwrap();
+            assert_eq!(lines, vec!["GET / HTTP/1.1"]);
+        }
+
+        #[test]
+        fn multipart_too_large() {
+            let body =
+                "--b\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n0123456789\r\n--b--";
+            let request = format!(
+                "POST / HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b\r\n\
+                 Content-Length: {}\r\n\r\n{body}",
+                body.len()
+            );
+            let limits = RequestLimits {
+                multipart: MultipartLimits {
+                    max_part_size: 4,
+                    ..MultipartLimits::default()
+                },
+                ..RequestLimits::default()
+            };
+            assert!(matches!(
+                split_request(&mut Cursor::new(request), &limits),
+                Err(ReadError::TooLarge)
+            ));
+        }
+
+        #[test]
+        fn header_limit() {
+            let limits = RequestLimits {
+                max_header_bytes: 40,
+                ..RequestLimits::default()
+            };
+            let mut reader = Cursor::new("GET / HTTP/1.1\r\nHost: localhost:8000\r\n\r\n");
+            assert!(split_request(&mut reader, &limits).is_ok());
+
+            for request in [
+                "GET / HTTP/1.1\r\nHost: localhost:8000\r\nAccept: */*\r\n\r\n",
+                "GET /a-very-long-path-that-never-seems-to-end HTTP/1.1\r\n\r\n",
+            ] {
+                assert!(matches!(
+                    split_request(&mut Cursor::new(request), &limits),
+                    Err(ReadError::HeadersTooLarge)
+                ));
+            }
+
+            // bare LF line endings cost one byte, not two, and may fill the
+            // budget exactly
+            let request = format!("GET / HTTP/1.1\nX-Fill: {}\n\n", "a".repeat(15));
+            assert_eq!(request.len(), 40);
+            assert!(split_request(&mut Cursor::new(&request), &limits).is_ok());
+            let limits_below = RequestLimits {
+                max_header_bytes: 39,
+                ..RequestLimits::default()
+            };
+            assert!(matches!(
+                split_request(&mut Cursor::new(&request), &limits_below),
+                Err(ReadError::HeadersTooLarge)
+            ));
+            let request = format!("GET / HTTP/1.1\nX-Fill: {}\n\n", "a".repeat(16));
+            assert!(matches!(
+                split_request(&mut Cursor::new(&request), &limits),
+                Err(ReadError::HeadersTooLarge)
+            ));
+
+            // trailers count towards the same budget
+            let limits = RequestLimits {
+                max_header_bytes: 60,
+                ..RequestLimits::default()
+            };
+            let mut reader = Cursor::new(
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nExpires: never\r\n\r\n",
+            );
+            assert!(matches!(
+                split_request(&mut reader, &limits),
+                Err(ReadError::HeadersTooLarge)
+            ));
+        }
+
+        #[test]
+        fn body_limit() {
+            let limits = RequestLimits {
+                max_body_bytes: 4,
+                ..RequestLimits::default()
+            };
+            let mut reader = Cursor::new("POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\ntest");
+            assert!(split_request(&mut reader, &limits).is_ok());
+
+            for request in [
+                "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n",
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n",
+            ] {
+                assert!(matches!(
+                    split_request(&mut Cursor::new(request), &limits),
+                    Err(ReadError::TooLarge)
+                ));
+            }
+        }
+
+        // a reader whose data has run dry but whose peer is still connected
+        struct Stalled<'a>(&'a [u8]);
 
-    let mut headers = HashMap::new();
+        impl Read for Stalled<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+                let size = buf.len().min(self.0.len());
+                buf[..size].copy_from_slice(&self.0[..size]);
+                self.0 = &self.0[size..];
+                Ok(size)
+            }
+        }
 
-    for (header, value) in header_vector {
-        headers.insert(header, value);
+        #[test]
+        fn slow_clients_time_out() {
+            for request in [
+                "GET / HTTP/1.1\r\nHost: loc",
+                "POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort",
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nab",
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nab\r\n",
+            ] {
+                let mut reader = io::BufReader::new(Stalled(request.as_bytes()));
+                assert!(matches!(
+                    split_request(&mut reader, &RequestLimits::default()),
+                    Err(ReadError::TimedOut)
+                ));
+            }
+        }
     }
 
-    match (method, uri) {
-        (method, Some(uri)) => Ok(HttpRequest {
-            method: method.to_string(),
-            uri: uri.to_string(),
-            headers,
-            body: parse_json(&body),
-        }),
-        (_, _) => Err(parse_error),
+    mod parse_request {
+        use super::super::*;
+        use crate::parsers::json::JsonObject;
+
+        fn lines(lines: &[&str]) -> Vec<String> {
+            lines.iter().map(|line| line.to_string()).collect()
+        }
+
+        #[test]
+        fn splits_path_and_query() {
+            let request = parse_request(
+                lines(&["GET /search%20results?tag=a&tag=b+c&q=%C3%A9 HTTP/1.1"]),
+                RequestBody::Bytes(Vec::new()),
+            )
+            .ok()
+            .unwrap();
+            assert_eq!(request.uri, "/search%20results?tag=a&tag=b+c&q=%C3%A9");
+            assert_eq!(request.path, "/search results");
+            assert_eq!(request.query("tag"), Some("a"));
+            assert_eq!(request.query_all("tag"), ["a", "b c"]);
+            assert_eq!(request.query("q"), Some("é"));
+            assert_eq!(request.query("missing"), None);
+            assert!(request.query_all("missing").is_empty());
+        }
+
+        #[test]
+        fn rejects_bad_encoding() {
+            let response = parse_request(
+                lines(&["GET /a%zz HTTP/1.1"]),
+                RequestBody::Bytes(Vec::new()),
+            )
+            .err()
+            .unwrap();
+            assert_eq!(response.code.value(), 400);
+        }
+
+        #[test]
+        fn rejects_unknown_versions() {
+            let response =
+                parse_request(lines(&["GET / HTTP/2.0"]), RequestBody::Bytes(Vec::new()))
+                    .err()
+                    .unwrap();
+            assert_eq!(response.code.value(), 505);
+            assert!(parse_request(lines(&["GET /"]), RequestBody::Bytes(Vec::new())).is_err());
+        }
+
+        #[test]
+        fn binary_body() {
+            let body = vec![0x89, b'P', b'N', b'G', 0xff, 0x00];
+            let request = parse_request(
+                lines(&["POST / HTTP/1.1"]),
+                RequestBody::Bytes(body.clone()),
+            )
+            .ok()
+            .unwrap();
+            assert_eq!(request.bytes(), body.as_slice());
+            assert_eq!(request.text(), None);
+            assert_eq!(request.json().err().unwrap().code.value(), 415);
+        }
+
+        #[test]
+        fn typed_body_accessors() {
+            let request = parse_request(
+                lines(&[
+                    "POST / HTTP/1.1",
+                    "Content-Type: application/json; charset=utf-8",
+                ]),
+                RequestBody::Bytes(br#"{"a": [1, 2]}"#.to_vec()),
+            )
+            .ok()
+            .unwrap();
+            assert_eq!(request.text(), Some(r#"{"a": [1, 2]}"#));
+            assert_eq!(
+                request.json().ok(),
+                Some(&JsonValue::Object(JsonObject::from([(
+                    "a".to_string(),
+                    JsonValue::Array(vec![JsonValue::Int(1), JsonValue::Int(2)])
+                )])))
+            );
+        }
+
+        #[test]
+        fn json_with_raw_numbers() {
+            let request = parse_request(
+                lines(&["POST / HTTP/1.1", "Content-Type: application/json"]),
+                RequestBody::Bytes(br#"{"amount": 1234567890123456789.01}"#.to_vec()),
+            )
+            .ok()
+            .unwrap();
+            let value = request
+                .json_with(&ParseOptions::default().raw_numbers(true))
+                .ok()
+                .unwrap();
+            assert_eq!(value.to_string(), r#"{"amount":1234567890123456789.01}"#);
+
+            let request = parse_request(
+                lines(&["POST / HTTP/1.1", "Content-Type: text/plain"]),
+                RequestBody::Bytes(b"1".to_vec()),
+            )
+            .ok()
+            .unwrap();
+            let response = request.json_with(&ParseOptions::default()).err().unwrap();
+            assert_eq!(response.code.value(), 415);
+        }
+
+        #[test]
+        fn raw_numbers_decide_how_numbers_are_decoded() {
+            let request = parse_request(
+                lines(&["POST / HTTP/1.1", "Content-Type: application/json"]),
+                RequestBody::Bytes(b"[1e400]".to_vec()),
+            )
+            .ok()
+            .unwrap();
+            assert_eq!(request.json().err().unwrap().code.value(), 400);
+            let value = request
+                .json_with(&ParseOptions::default().raw_numbers(true))
+                .ok()
+                .unwrap();
+            assert_eq!(value.to_string(), "[1e400]");
+        }
+
+        #[test]
+        fn rejects_malformed_json() {
+            let request = parse_request(
+                lines(&["POST / HTTP/1.1", "Content-Type: application/problem+json"]),
+                RequestBody::Bytes(b"{\"a\": [1,\n 2,]}".to_vec()),
+            )
+            .ok()
+            .unwrap();
+            let response = request.json().err().unwrap();
+            assert_eq!(response.code.value(), 400);
+            match response.body {
+                response::Body::Full(body) => assert_eq!(
+                    String::from_utf8(body).unwrap(),
+                    "\"request body is not valid JSON: trailing comma at line 2, column 3 (byte 12)\""
+                ),
+                _ => panic!("expected a full body"),
+            }
+        }
+
+        #[test]
+        fn keeps_other_media_types_raw() {
+            let request = parse_request(
+                lines(&["POST / HTTP/1.1", "Content-Type: text/csv"]),
+                RequestBody::Bytes(b"{not json".to_vec()),
+            )
+            .ok()
+            .unwrap();
+            assert_eq!(request.media_type().as_deref(), Some("text/csv"));
+            assert_eq!(request.text(), Some("{not json"));
+            assert_eq!(request.json().err().unwrap().code.value(), 415);
+        }
+
+        #[test]
+        fn decodes_urlencoded_form() {
+            let request = parse_request(
+                lines(&[
+                    "POST / HTTP/1.1",
+                    "Content-Type: application/x-www-form-urlencoded",
+                ]),
+                RequestBody::Bytes(b"name=Ada+Lovelace&topic=math&topic=%E2%88%9E".to_vec()),
+            )
+            .ok()
+            .unwrap();
+            assert_eq!(request.form("name"), Some("Ada Lovelace"));
+            assert_eq!(request.form_all("topic"), ["math", "∞"]);
+            assert_eq!(request.form("missing"), None);
+            assert_eq!(request.json().err().unwrap().code.value(), 415);
+        }
+
+        #[test]
+        fn rejects_malformed_form() {
+            let response = parse_request(
+                lines(&[
+                    "POST / HTTP/1.1",
+                    "Content-Type: application/x-www-form-urlencoded",
+                ]),
+                RequestBody::Bytes(b"name=%G1".to_vec()),
+            )
+            .err()
+            .unwrap();
+            assert_eq!(response.code.value(), 400);
+        }
+
+        #[test]
+        fn multipart_fields_and_files() {
+            let body = "--b\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nHoliday\r\n\
+                        --b\r\nContent-Disposition: form-data; name=\"photo\"; filename=\"a.png\"\r\n\
+                        Content-Type: image/png\r\n\r\n\x01PNG\r\n--b--";
+            let parts =
+                multipart::parse_multipart(body.as_bytes(), "b", &MultipartLimits::default())
+                    .unwrap();
+            let request = parse_request(
+                lines(&[
+                    "POST / HTTP/1.1",
+                    "Content-Type: multipart/form-data; boundary=b",
+                ]),
+                RequestBody::Multipart(parts),
+            )
+            .ok()
+            .unwrap();
+            assert_eq!(request.form("title"), Some("Holiday"));
+            assert_eq!(request.files().len(), 1);
+            let photo = request.file("photo").unwrap();
+            assert_eq!(photo.filename.as_deref(), Some("a.png"));
+            assert_eq!(photo.bytes().unwrap(), b"\x01PNG");
+            assert!(request.file("title").is_none());
+        }
+
+        #[test]
+        fn multipart_without_boundary() {
+            let response = parse_request(
+                lines(&["POST / HTTP/1.1", "Content-Type: multipart/form-data"]),
+                RequestBody::Bytes(b"--b--".to_vec()),
+            )
+            .err()
+            .unwrap();
+            assert_eq!(response.code.value(), 400);
+        }
+
+        #[test]
+        fn empty_json_body() {
+            let request = parse_request(
+                lines(&["POST / HTTP/1.1", "Content-Type: application/json"]),
+                RequestBody::Bytes(Vec::new()),
+            )
+            .ok()
+            .unwrap();
+            assert_eq!(request.json().err().unwrap().code.value(), 400);
+        }
+
+        #[test]
+        fn keeps_every_header_field() {
+            let request = parse_request(
+                lines(&[
+                    "GET / HTTP/1.1",
+                    "Host: localhost:8000",
+                    "Accept: text/html",
+                    "accept: application/json",
+                ]),
+                RequestBody::Bytes(Vec::new()),
+            )
+            .ok()
+            .unwrap();
+            assert_eq!(request.header("host"), Some("localhost:8000"));
+            assert_eq!(
+                request.headers.get_all("Accept").collect::<Vec<_>>(),
+                ["text/html", "application/json"]
+            );
+        }
+
+        #[test]
+        fn keep_alive_defaults() {
+            let keep_alive = |request_lines: &[&str]| {
+                parse_request(lines(request_lines), RequestBody::Bytes(Vec::new()))
+                    .ok()
+                    .unwrap()
+                    .keep_alive()
+            };
+            assert!(keep_alive(&["GET / HTTP/1.1"]));
+            assert!(!keep_alive(&["GET / HTTP/1.1", "Connection: close"]));
+            assert!(!keep_alive(&["GET / HTTP/1.0"]));
+            assert!(keep_alive(&["GET / HTTP/1.0", "connection: Keep-Alive"]));
+        }
     }
 }
@@ -1,64 +1,576 @@
-use std::{io::Write, net::TcpStream};
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::TcpStream,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use super::json::JsonValue;
+use super::{headers::HeaderMap, json::JsonValue};
 
-pub enum HttpCode {
-    Ok,
-    BadRequest,
-    NotFound,
-    UnknownError,
-}
+// every status code gets its number and canonical reason phrase in one place,
+// the enum, `value`, `reason` and `TryFrom<u16>` are all generated from this list
+macro_rules! http_codes {
+    ($($variant:ident = $value:literal, $reason:literal;)*) => {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub enum HttpCode {
+            $($variant,)*
+            Custom(CustomCode),
+        }
+
+        impl HttpCode {
+            pub fn value(&self) -> u16 {
+                match self {
+                    $(Self::$variant => $value,)*
+                    Self::Custom(code) => code.0,
+                }
+            }
+
+            pub fn reason(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => $reason,)*
+                    Self::Custom(_) => "",
+                }
+            }
+        }
+
+        impl TryFrom<u16> for HttpCode {
+            type Error = InvalidHttpCode;
 
-impl HttpCode {
-    fn value(&self) -> i32 {
-        match &self {
-            Self::Ok => 200,
-            Self::BadRequest => 400,
-            Self::NotFound => 404,
-            _ => 300,
+            fn try_from(value: u16) -> Result<HttpCode, InvalidHttpCode> {
+                match value {
+                    $($value => Ok(Self::$variant),)*
+                    100..=599 => Ok(Self::Custom(CustomCode(value))),
+                    value => Err(InvalidHttpCode(value)),
+                }
+            }
         }
+    };
+}
+
+// a code without a variant of its own, only `HttpCode::try_from` hands these
+// out so the status line always gets three digits from 100 to 599
+// (RFC 9110 section 15)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CustomCode(u16);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidHttpCode(pub u16);
+
+impl fmt::Display for InvalidHttpCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is not a status code between 100 and 599", self.0)
+    }
+}
+
+http_codes! {
+    Continue = 100, "Continue";
+    SwitchingProtocols = 101, "Switching Protocols";
+    Ok = 200, "OK";
+    Created = 201, "Created";
+    Accepted = 202, "Accepted";
+    NonAuthoritativeInformation = 203, "Non-Authoritative Information";
+    NoContent = 204, "No Content";
+    ResetContent = 205, "Reset Content";
+    PartialContent = 206, "Partial Content";
+    MultipleChoices = 300, "Multiple Choices";
+    MovedPermanently = 301, "Moved Permanently";
+    Found = 302, "Found";
+    SeeOther = 303, "See Other";
+    NotModified = 304, "Not Modified";
+    UseProxy = 305, "Use Proxy";
+    TemporaryRedirect = 307, "Temporary Redirect";
+    PermanentRedirect = 308, "Permanent Redirect";
+    BadRequest = 400, "Bad Request";
+    Unauthorized = 401, "Unauthorized";
+    PaymentRequired = 402, "Payment Required";
+    Forbidden = 403, "Forbidden";
+    NotFound = 404, "Not Found";
+    MethodNotAllowed = 405, "Method Not Allowed";
+    NotAcceptable = 406, "Not Acceptable";
+    ProxyAuthenticationRequired = 407, "Proxy Authentication Required";
+    RequestTimeout = 408, "Request Timeout";
+    Conflict = 409, "Conflict";
+    Gone = 410, "Gone";
+    LengthRequired = 411, "Length Required";
+    PreconditionFailed = 412, "Precondition Failed";
+    ContentTooLarge = 413, "Content Too Large";
+    UriTooLong = 414, "URI Too Long";
+    UnsupportedMediaType = 415, "Unsupported Media Type";
+    RangeNotSatisfiable = 416, "Range Not Satisfiable";
+    ExpectationFailed = 417, "Expectation Failed";
+    MisdirectedRequest = 421, "Misdirected Request";
+    UnprocessableContent = 422, "Unprocessable Content";
+    UpgradeRequired = 426, "Upgrade Required";
+    PreconditionRequired = 428, "Precondition Required";
+    TooManyRequests = 429, "Too Many Requests";
+    RequestHeaderFieldsTooLarge = 431, "Request Header Fields Too Large";
+    InternalServerError = 500, "Internal Server Error";
+    NotImplemented = 501, "Not Implemented";
+    BadGateway = 502, "Bad Gateway";
+    ServiceUnavailable = 503, "Service Unavailable";
+    GatewayTimeout = 504, "Gateway Timeout";
+    HttpVersionNotSupported = 505, "HTTP Version Not Supported";
+    NetworkAuthenticationRequired = 511, "Network Authentication Required";
+}
+
+impl fmt::Display for HttpCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason())
     }
 }
 
-impl ToString for HttpCode {
-    fn to_string(&self) -> String {
-        match &self {
-            Self::Ok => "OK".to_string(),
-            Self::BadRequest => "BAD REQUEST".to_string(),
-            Self::NotFound => "NOT FOUND".to_string(),
-            _ => "".to_string(),
+const SERVER: &str = concat!("rust_web/", env!("CARGO_PKG_VERSION"));
+
+pub enum Body {
+    Full(Vec<u8>),
+    // streamed straight from the reader, with Content-Length when the size
+    // is known up front and chunked transfer coding otherwise
+    Reader(Box<dyn Read>, Option<u64>),
+    // each item goes out as its own chunk
+    Chunks(Box<dyn Iterator<Item = Vec<u8>>>),
+}
+
+impl Body {
+    pub fn reader<R: Read + 'static>(reader: R, length: Option<u64>) -> Body {
+        Body::Reader(Box::new(reader), length)
+    }
+
+    pub fn chunks<I>(chunks: I) -> Body
+    where
+        I: IntoIterator,
+        I::Item: Into<Vec<u8>> + 'static,
+        I::IntoIter: 'static,
+    {
+        Body::Chunks(Box::new(chunks.into_iter().map(Into::into)))
+    }
+
+    pub fn length(&self) -> Option<u64> {
+        match self {
+            Body::Full(bytes) => Some(bytes.len() as u64),
+            Body::Reader(_, length) => *length,
+            Body::Chunks(_) => None,
         }
     }
 }
 
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Body {
+        Body::Full(bytes)
+    }
+}
+
+impl From<&[u8]> for Body {
+    fn from(bytes: &[u8]) -> Body {
+        Body::Full(bytes.to_vec())
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Body {
+        Body::Full(text.into_bytes())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Body {
+        Body::Full(text.as_bytes().to_vec())
+    }
+}
+
+impl From<JsonValue> for Body {
+    fn from(value: JsonValue) -> Body {
+        Body::Full(value.to_string().into_bytes())
+    }
+}
+
 pub struct HttpResponse {
     pub code: HttpCode,
-    pub body: Box<dyn ToString>,
+    pub headers: HeaderMap,
+    pub body: Body,
 }
 
-pub fn http_error(code: HttpCode, error_message: &str) -> HttpResponse {
-    match code {
-        HttpCode::BadRequest => HttpResponse {
-            code: HttpCode::BadRequest,
-            body: Box::new(JsonValue::String(error_message.to_string())),
-        },
-        HttpCode::NotFound => HttpResponse {
-            code: HttpCode::NotFound,
-            body: Box::new(JsonValue::String(error_message.to_string())),
-        },
-        _ => HttpResponse {
-            code: HttpCode::UnknownError,
-            body: Box::new(JsonValue::String(error_message.to_string())),
-        },
+impl HttpResponse {
+    pub fn json(code: HttpCode, value: JsonValue) -> HttpResponse {
+        HttpResponse::with_content_type(code, "application/json", value)
+    }
+
+    pub fn html<T: Into<Body>>(code: HttpCode, html: T) -> HttpResponse {
+        HttpResponse::with_content_type(code, "text/html; charset=utf-8", html)
+    }
+
+    pub fn text<T: Into<Body>>(code: HttpCode, text: T) -> HttpResponse {
+        HttpResponse::with_content_type(code, "text/plain; charset=utf-8", text)
+    }
+
+    pub fn bytes<T: Into<Body>>(code: HttpCode, content_type: &str, bytes: T) -> HttpResponse {
+        HttpResponse::with_content_type(code, content_type, bytes)
+    }
+
+    fn with_content_type<T: Into<Body>>(
+        code: HttpCode,
+        content_type: &str,
+        body: T,
+    ) -> HttpResponse {
+        let mut headers = HeaderMap::new();
+        headers.append("Content-Type", content_type);
+        HttpResponse {
+            code,
+            headers,
+            body: body.into(),
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
     }
 }
 
+pub fn http_error(code: HttpCode, error_message: &str) -> HttpResponse {
+    HttpResponse::json(code, JsonValue::String(error_message.to_string()))
+}
+
 pub fn send_response(mut stream: TcpStream, response: HttpResponse) {
-    let status_line = format!(
-        "HTTP/1.1 {} {}\r\n\r\n",
-        response.code.value(),
-        response.code.to_string()
-    );
-    let response = format!("{status_line}\r\n{}", response.body.to_string());
-    stream.write_all(response.as_bytes()).unwrap();
+    // the client may already have hung up, there is nobody left to tell
+    let _ = write_response(&mut stream, response, false, true);
+}
+
+// HTTP/1.0 clients don't understand chunked coding, without it a body of
+// unknown length is only delimited by closing the connection afterwards
+pub fn write_response<W: Write>(
+    writer: &mut W,
+    response: HttpResponse,
+    head_request: bool,
+    chunked_allowed: bool,
+) -> io::Result<()> {
+    let code = response.code.value();
+    // 1xx, 204 and 304 responses never carry content (RFC 9110 section 6.4.1)
+    let has_body = !(100..200).contains(&code) && code != 204 && code != 304;
+    let length = response.body.length();
+    let chunked = has_body && length.is_none() && chunked_allowed;
+
+    let mut head = format!("HTTP/1.1 {} {}\r\n", code, response.code);
+    for (header, value) in &response.headers {
+        head.push_str(&format!("{header}: {value}\r\n"));
+    }
+    if response.header("Date").is_none() {
+        head.push_str(&format!("Date: {}\r\n", http_date(SystemTime::now())));
+    }
+    if response.header("Server").is_none() {
+        head.push_str(&format!("Server: {SERVER}\r\n"));
+    }
+    if has_body {
+        if response.header("Content-Type").is_none() && length != Some(0) {
+            head.push_str("Content-Type: text/plain; charset=utf-8\r\n");
+        }
+        match length {
+            Some(length) => head.push_str(&format!("Content-Length: {length}\r\n")),
+            None if chunked => head.push_str("Transfer-Encoding: chunked\r\n"),
+            None => (),
+        }
+    }
+    head.push_str("\r\n");
+    writer.write_all(head.as_bytes())?;
+
+    // HEAD gets the framing headers the GET would have had but no content,
+    // the client won't read any
+    if has_body && !head_request {
+        match response.body {
+            Body::Full(bytes) => writer.write_all(&bytes)?,
+            Body::Reader(reader, Some(length)) => {
+                let copied = io::copy(&mut reader.take(length), writer)?;
+                if copied != length {
+                    // the promised length can't be honoured any more, the
+                    // connection has to be dropped instead
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "response body ended early",
+                    ));
+                }
+            }
+            Body::Reader(mut reader, None) => {
+                let mut buffer = [0; 8192];
+                loop {
+                    let read = match reader.read(&mut buffer) {
+                        Ok(0) => break,
+                        Ok(read) => read,
+                        Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(error) => return Err(error),
+                    };
+                    write_chunk(writer, &buffer[..read], chunked)?;
+                }
+                finish_chunks(writer, chunked)?;
+            }
+            Body::Chunks(chunks) => {
+                for chunk in chunks {
+                    write_chunk(writer, &chunk, chunked)?;
+                }
+                finish_chunks(writer, chunked)?;
+            }
+        }
+    }
+    writer.flush()
+}
+
+fn write_chunk<W: Write>(writer: &mut W, data: &[u8], chunked: bool) -> io::Result<()> {
+    // an empty chunk would read as the end of the body
+    if data.is_empty() {
+        return Ok(());
+    }
+    if chunked {
+        writer.write_all(format!("{:X}\r\n", data.len()).as_bytes())?;
+    }
+    writer.write_all(data)?;
+    if chunked {
+        writer.write_all(b"\r\n")?;
+    }
+    Ok(())
+}
+
+fn finish_chunks<W: Write>(writer: &mut W, chunked: bool) -> io::Result<()> {
+    if chunked {
+        writer.write_all(b"0\r\n\r\n")?;
+    }
+    Ok(())
+}
+
+// IMF-fixdate from RFC 9110 section 5.6.7, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+pub fn http_date(time: SystemTime) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let days = seconds / 86_400;
+    let seconds_of_day = seconds % 86_400;
+
+    // civil-from-days, counting eras of 400 years starting at 0000-03-01
+    let shifted_days = days + 719_468;
+    let era = shifted_days / 146_097;
+    let day_of_era = shifted_days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        seconds_of_day / 3_600,
+        seconds_of_day % 3_600 / 60,
+        seconds_of_day % 60
+    )
+}
+
+pub fn service_unavailable(retry_after_seconds: u32) -> HttpResponse {
+    let mut response = http_error(HttpCode::ServiceUnavailable, "server is overloaded");
+    response
+        .headers
+        .append("Retry-After", &retry_after_seconds.to_string());
+    response.headers.append("Connection", "close");
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    mod write_response {
+        use super::super::*;
+
+        fn written(response: HttpResponse) -> String {
+            let mut output: Vec<u8> = Vec::new();
+            write_response(&mut output, response, false, true).unwrap();
+            String::from_utf8(output).unwrap()
+        }
+
+        #[test]
+        fn writes_binary_body() {
+            let mut output: Vec<u8> = Vec::new();
+            let response = HttpResponse::bytes(HttpCode::Ok, "image/png", vec![0x89, 0xff, 0x00]);
+            write_response(&mut output, response, false, true).unwrap();
+            assert!(output.ends_with(b"Content-Length: 3\r\n\r\n\x89\xff\x00"));
+        }
+
+        #[test]
+        fn frames_body_with_headers() {
+            let output = written(HttpResponse::json(
+                HttpCode::Ok,
+                JsonValue::String("hi".to_string()),
+            ));
+            let (head, body) = output.split_once("\r\n\r\n").unwrap();
+            let mut lines = head.split("\r\n");
+
+            assert_eq!(lines.next(), Some("HTTP/1.1 200 OK"));
+            let headers: Vec<&str> = lines.collect();
+            assert!(headers.contains(&"Content-Type: application/json"));
+            assert!(headers.contains(&"Content-Length: 4"));
+            assert!(headers.iter().any(|header| header.starts_with("Date: ")));
+            assert!(headers
+                .iter()
+                .any(|header| header.starts_with("Server: rust_web/")));
+            assert_eq!(body, "\"hi\"");
+        }
+
+        #[test]
+        fn keeps_explicit_headers() {
+            let mut response = HttpResponse::text(HttpCode::Ok, "hello");
+            response.headers.append("server", "custom");
+            let output = written(response);
+            assert!(output.contains("server: custom\r\n"));
+            assert!(!output.contains("Server: rust_web"));
+        }
+
+        #[test]
+        fn omits_body_for_no_content() {
+            let output = written(HttpResponse::text(HttpCode::NoContent, "ignored"));
+            assert!(output.starts_with("HTTP/1.1 204 No Content\r\n"));
+            assert!(!output.contains("Content-Length"));
+            assert!(output.ends_with("\r\n\r\n"));
+        }
+
+        #[test]
+        fn omits_body_for_head() {
+            let mut output: Vec<u8> = Vec::new();
+            let response = HttpResponse::text(HttpCode::MethodNotAllowed, "not here");
+            write_response(&mut output, response, true, true).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("Content-Length: 8\r\n"));
+            assert!(output.ends_with("\r\n\r\n"));
+
+            let mut output: Vec<u8> = Vec::new();
+            let rows = Body::chunks(["a", "b"]);
+            write_response(
+                &mut output,
+                HttpResponse::text(HttpCode::Ok, rows),
+                true,
+                true,
+            )
+            .unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("Transfer-Encoding: chunked\r\n"));
+            assert!(output.ends_with("\r\n\r\n"));
+        }
+
+        #[test]
+        fn streams_reader_with_known_length() {
+            let body = Body::reader("0123456789".as_bytes(), Some(4));
+            let output = written(HttpResponse::text(HttpCode::Ok, body));
+            assert!(output.contains("Content-Length: 4\r\n"));
+            assert!(!output.contains("Transfer-Encoding"));
+            assert!(output.ends_with("\r\n\r\n0123"));
+        }
+
+        #[test]
+        fn fails_when_reader_ends_early() {
+            let body = Body::reader("abc".as_bytes(), Some(10));
+            let mut output: Vec<u8> = Vec::new();
+            let response = HttpResponse::text(HttpCode::Ok, body);
+            assert!(write_response(&mut output, response, false, true).is_err());
+        }
+
+        #[test]
+        fn streams_chunks() {
+            let rows = (1..=3).map(|row| format!("{row},row {row}\n"));
+            let output = written(HttpResponse::text(HttpCode::Ok, Body::chunks(rows)));
+            assert!(output.contains("Transfer-Encoding: chunked\r\n"));
+            assert!(!output.contains("Content-Length"));
+            assert!(output.ends_with(
+                "\r\n\r\n8\r\n1,row 1\n\r\n8\r\n2,row 2\n\r\n8\r\n3,row 3\n\r\n0\r\n\r\n"
+            ));
+        }
+
+        #[test]
+        fn streams_unknown_length_reader_unchunked() {
+            let body = Body::reader("raw bytes".as_bytes(), None);
+            let mut output: Vec<u8> = Vec::new();
+            write_response(
+                &mut output,
+                HttpResponse::text(HttpCode::Ok, body),
+                false,
+                false,
+            )
+            .unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(!output.contains("Transfer-Encoding"));
+            assert!(output.ends_with("\r\n\r\nraw bytes"));
+        }
+    }
+
+    mod http_date {
+        use super::super::*;
+        use std::time::Duration;
+
+        #[test]
+        fn epoch() {
+            assert_eq!(http_date(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+        }
+
+        #[test]
+        fn rfc_example() {
+            let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+            assert_eq!(http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        }
+
+        #[test]
+        fn leap_day() {
+            let time = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+            assert_eq!(http_date(time), "Thu, 29 Feb 2024 12:34:56 GMT");
+        }
+    }
+
+    mod http_code {
+        use super::super::*;
+
+        #[test]
+        fn values_and_reasons() {
+            assert_eq!(HttpCode::Ok.value(), 200);
+            assert_eq!(HttpCode::Ok.reason(), "OK");
+            assert_eq!(HttpCode::PermanentRedirect.value(), 308);
+            assert_eq!(HttpCode::TooManyRequests.reason(), "Too Many Requests");
+            assert_eq!(
+                HttpCode::ServiceUnavailable.to_string(),
+                "Service Unavailable"
+            );
+        }
+
+        #[test]
+        fn from_number() {
+            assert_eq!(HttpCode::try_from(415), Ok(HttpCode::UnsupportedMediaType));
+            assert_eq!(
+                HttpCode::try_from(299),
+                Ok(HttpCode::Custom(CustomCode(299)))
+            );
+        }
+
+        #[test]
+        fn custom_codes() {
+            let code = HttpCode::try_from(299).unwrap();
+            assert_eq!(code.value(), 299);
+            assert_eq!(code.reason(), "");
+            assert_eq!(HttpCode::try_from(599).unwrap().value(), 599);
+        }
+
+        #[test]
+        fn rejects_out_of_range() {
+            for value in [0, 42, 99, 600, 1000] {
+                assert_eq!(HttpCode::try_from(value), Err(InvalidHttpCode(value)));
+            }
+            assert_eq!(
+                InvalidHttpCode(42).to_string(),
+                "42 is not a status code between 100 and 599"
+            );
+        }
+    }
 }
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+// `+` only means a space inside query strings and form bodies, never in paths
+pub fn percent_decode(input: &str, plus_as_space: bool) -> Option<String> {
+    let mut bytes: Vec<u8> = Vec::with_capacity(input.len());
+    let mut input_bytes = input.bytes();
+
+    while let Some(byte) = input_bytes.next() {
+        match byte {
+            b'%' => {
+                let high = hex_value(input_bytes.next()?)?;
+                let low = hex_value(input_bytes.next()?)?;
+                bytes.push(high << 4 | low);
+            }
+            b'+' if plus_as_space => bytes.push(b' '),
+            byte => bytes.push(byte),
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+pub fn parse_urlencoded(input: &str) -> Option<HashMap<String, Vec<String>>> {
+    let mut values: HashMap<String, Vec<String>> = HashMap::new();
+
+    for pair in input.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        values
+            .entry(percent_decode(key, true)?)
+            .or_default()
+            .push(percent_decode(value, true)?);
+    }
+
+    Some(values)
+}
+
+#[cfg(test)]
+mod tests {
+    mod percent_decode {
+        use super::super::*;
+
+        #[test]
+        fn plain_text() {
+            assert_eq!(percent_decode("hello", true).unwrap(), "hello");
+        }
+
+        #[test]
+        fn escapes() {
+            assert_eq!(percent_decode("a%20b%2Fc", false).unwrap(), "a b/c");
+            assert_eq!(percent_decode("caf%C3%A9", false).unwrap(), "café");
+        }
+
+        #[test]
+        fn plus_sign() {
+            assert_eq!(percent_decode("a+b", true).unwrap(), "a b");
+            assert_eq!(percent_decode("a+b", false).unwrap(), "a+b");
+        }
+
+        #[test]
+        fn malformed_escapes() {
+            assert!(percent_decode("100%", false).is_none());
+            assert!(percent_decode("%zz", false).is_none());
+            assert!(percent_decode("%FF", false).is_none());
+        }
+    }
+
+    mod parse_urlencoded {
+        use super::super::*;
+
+        #[test]
+        fn empty_string() {
+            assert!(parse_urlencoded("").unwrap().is_empty());
+        }
+
+        #[test]
+        fn multiple_values() {
+            let values = parse_urlencoded("tag=a&name=J%C3%BCrgen+K&tag=b&flag").unwrap();
+            assert_eq!(values["tag"], vec!["a", "b"]);
+            assert_eq!(values["name"], vec!["Jürgen K"]);
+            assert_eq!(values["flag"], vec![""]);
+        }
+
+        #[test]
+        fn bad_encoding() {
+            assert!(parse_urlencoded("a=%2").is_none());
+        }
+    }
+}
@@ -1,58 +1,390 @@
-use std::{fs, net::TcpStream};
-
 use crate::parsers::{
-    json::JsonValue,
+    headers::HeaderMap,
     request::HttpRequest,
-    response::{self, HttpCode, HttpResponse},
+    response::{self, Body, HttpCode, HttpResponse},
 };
 
-pub fn handle_request(request: HttpRequest, stream: TcpStream) {
-    let HttpRequest {
-        method,
-        uri,
-        headers,
-        body,
-    } = request;
-
-    match (method.as_str(), uri.as_str()) {
-        ("GET", "/") => response::send_response(
-            stream,
-            hello_world(HttpRequest {
-                method,
-                uri,
-                headers,
-                body,
-            }),
-        ),
-        ("POST", "/") => response::send_response(
-            stream,
-            test_post(HttpRequest {
-                method,
-                uri,
-                headers,
-                body,
-            }),
-        ),
-        _ => response::send_response(
-            stream,
-            HttpResponse {
-                code: HttpCode::NotFound,
-                body: Box::new(fs::read_to_string("./src/404.html").unwrap()),
-            },
-        ),
-    }
-}
-
-fn hello_world(_: HttpRequest) -> HttpResponse {
-    HttpResponse {
-        code: HttpCode::Ok,
-        body: Box::new(fs::read_to_string("./src/hello.html").unwrap()),
-    }
-}
-
-fn test_post(request: HttpRequest) -> HttpResponse {
-    HttpResponse {
-        code: HttpCode::Ok,
-        body: Box::new(request.body.unwrap_or(JsonValue::Array(vec![]))),
+use std::collections::HashMap;
+
+type Handler = Box<dyn Fn(HttpRequest) -> HttpResponse + Send + Sync>;
+
+// one node per path segment, children are tried static first, then the
+// `:param` child and finally the `*wildcard` child so the most specific
+// route always wins regardless of registration order
+#[derive(Default)]
+struct Node {
+    handlers: HashMap<String, Handler>,
+    statics: HashMap<String, Node>,
+    param: Option<(String, Box<Node>)>,
+    wildcard: Option<(String, Box<Node>)>,
+}
+
+impl Node {
+    fn insert(&mut self, segments: &[&str], method: &str, handler: Handler) {
+        let (segment, rest) = match segments.split_first() {
+            Some(value) => value,
+            None => {
+                self.handlers.insert(method.to_string(), handler);
+                return;
+            }
+        };
+
+        if let Some(name) = segment.strip_prefix(':') {
+            let (existing, child) = self
+                .param
+                .get_or_insert_with(|| (name.to_string(), Box::default()));
+            assert!(
+                existing == name,
+                "parameter :{name} conflicts with :{existing} at the same position"
+            );
+            child.insert(rest, method, handler);
+        } else if let Some(name) = segment.strip_prefix('*') {
+            assert!(rest.is_empty(), "wildcard *{name} must be the last segment");
+            let (existing, child) = self
+                .wildcard
+                .get_or_insert_with(|| (name.to_string(), Box::default()));
+            assert!(
+                existing == name,
+                "wildcard *{name} conflicts with *{existing} at the same position"
+            );
+            child.insert(rest, method, handler);
+        } else {
+            self.statics
+                .entry(segment.to_string())
+                .or_default()
+                .insert(rest, method, handler);
+        }
+    }
+
+    fn find(&self, segments: &[&str], params: &mut Vec<(String, String)>) -> Option<&Node> {
+        let (segment, rest) = match segments.split_first() {
+            Some(value) => value,
+            None => return (!self.handlers.is_empty()).then_some(self),
+        };
+
+        if let Some(node) = self
+            .statics
+            .get(*segment)
+            .and_then(|child| child.find(rest, params))
+        {
+            return Some(node);
+        }
+
+        if let Some((name, child)) = &self.param {
+            if !segment.is_empty() {
+                params.push((name.clone(), segment.to_string()));
+                if let Some(node) = child.find(rest, params) {
+                    return Some(node);
+                }
+                params.pop();
+            }
+        }
+
+        match &self.wildcard {
+            Some((name, child)) => {
+                params.push((name.clone(), segments.join("/")));
+                Some(child)
+            }
+            None => None,
+        }
+    }
+}
+
+impl Node {
+    fn allow(&self) -> String {
+        let mut methods: Vec<&str> = self.handlers.keys().map(String::as_str).collect();
+        if self.handlers.contains_key("GET") && !self.handlers.contains_key("HEAD") {
+            methods.push("HEAD");
+        }
+        methods.push("OPTIONS");
+        methods.sort();
+        methods.join(", ")
+    }
+}
+
+fn split_path(path: &str) -> Vec<&str> {
+    path.strip_prefix('/').unwrap_or(path).split('/').collect()
+}
+
+pub struct Router {
+    root: Node,
+    not_found: Handler,
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            root: Node::default(),
+            not_found: Box::new(|_| response::http_error(HttpCode::NotFound, "not found")),
+        }
+    }
+
+    pub fn get<H>(&mut self, path: &str, handler: H) -> &mut Router
+    where
+        H: Fn(HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.route("GET", path, handler)
+    }
+
+    pub fn post<H>(&mut self, path: &str, handler: H) -> &mut Router
+    where
+        H: Fn(HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.route("POST", path, handler)
+    }
+
+    pub fn patch<H>(&mut self, path: &str, handler: H) -> &mut Router
+    where
+        H: Fn(HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.route("PATCH", path, handler)
+    }
+
+    pub fn delete<H>(&mut self, path: &str, handler: H) -> &mut Router
+    where
+        H: Fn(HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.route("DELETE", path, handler)
+    }
+
+    pub fn not_found<H>(&mut self, handler: H) -> &mut Router
+    where
+        H: Fn(HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.not_found = Box::new(handler);
+        self
+    }
+
+    // registering the same method and path again replaces the old handler
+    fn route<H>(&mut self, method: &str, path: &str, handler: H) -> &mut Router
+    where
+        H: Fn(HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.root
+            .insert(&split_path(path), method, Box::new(handler));
+        self
+    }
+
+    pub fn handle(&self, mut request: HttpRequest) -> HttpResponse {
+        let mut params = Vec::new();
+        let node = match self.root.find(&split_path(&request.path), &mut params) {
+            Some(node) => node,
+            None => return (self.not_found)(request),
+        };
+
+        // HEAD goes wherever GET does (RFC 9110 section 9.3.2), the body is
+        // dropped when the response is written
+        let handler = match node.handlers.get(&request.method) {
+            None if request.method == "HEAD" => node.handlers.get("GET"),
+            handler => handler,
+        };
+        match handler {
+            Some(handler) => {
+                request.params = params.into_iter().collect();
+                handler(request)
+            }
+            None if request.method == "OPTIONS" => {
+                let mut headers = HeaderMap::new();
+                headers.append("Allow", &node.allow());
+                HttpResponse {
+                    code: HttpCode::NoContent,
+                    headers,
+                    body: Body::from(""),
+                }
+            }
+            None => {
+                let mut response =
+                    response::http_error(HttpCode::MethodNotAllowed, "method not allowed");
+                response.headers.append("Allow", &node.allow());
+                response
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod handle {
+        use super::super::*;
+        use crate::parsers::json::JsonValue;
+        use std::{collections::HashMap, sync::OnceLock};
+
+        fn request(method: &str, uri: &str) -> HttpRequest {
+            HttpRequest {
+                method: method.to_string(),
+                uri: uri.to_string(),
+                version: "HTTP/1.1".to_string(),
+                path: uri.to_string(),
+                headers: HeaderMap::new(),
+                body: Vec::new(),
+                json: OnceLock::new(),
+                form: HashMap::new(),
+                files: Vec::new(),
+                query: HashMap::new(),
+                params: HashMap::new(),
+            }
+        }
+
+        fn body(response: HttpResponse) -> String {
+            match response.body {
+                Body::Full(bytes) => String::from_utf8(bytes).unwrap(),
+                _ => panic!("expected a full body"),
+            }
+        }
+
+        fn respond_with(text: &'static str) -> impl Fn(HttpRequest) -> HttpResponse {
+            move |_| HttpResponse::json(HttpCode::Ok, JsonValue::String(text.to_string()))
+        }
+
+        fn respond_with_params(request: HttpRequest) -> HttpResponse {
+            let mut params: Vec<String> = request
+                .params()
+                .iter()
+                .map(|(name, value)| format!("{name}={value}"))
+                .collect();
+            params.sort();
+            HttpResponse::json(HttpCode::Ok, JsonValue::String(params.join("&")))
+        }
+
+        #[test]
+        fn dispatches_on_method_and_path() {
+            let mut router = Router::new();
+            router
+                .get("/", respond_with("get"))
+                .post("/", respond_with("post"))
+                .delete("/items", respond_with("delete"));
+
+            assert_eq!(body(router.handle(request("GET", "/"))), "\"get\"");
+            assert_eq!(body(router.handle(request("POST", "/"))), "\"post\"");
+            assert_eq!(
+                body(router.handle(request("DELETE", "/items"))),
+                "\"delete\""
+            );
+        }
+
+        #[test]
+        fn falls_back_to_not_found() {
+            let mut router = Router::new();
+            router.get("/", respond_with("get"));
+            assert_eq!(router.handle(request("GET", "/missing")).code.value(), 404);
+
+            router.not_found(respond_with("custom"));
+            assert_eq!(
+                body(router.handle(request("PATCH", "/other"))),
+                "\"custom\""
+            );
+        }
+
+        #[test]
+        fn replaces_existing_route() {
+            let mut router = Router::new();
+            router
+                .get("/", respond_with("old"))
+                .get("/", respond_with("new"));
+            assert_eq!(body(router.handle(request("GET", "/"))), "\"new\"");
+        }
+
+        #[test]
+        fn captures_path_parameters() {
+            let mut router = Router::new();
+            router.get("/users/:id/posts/:post_id", respond_with_params);
+            assert_eq!(
+                body(router.handle(request("GET", "/users/7/posts/42"))),
+                "\"id=7&post_id=42\""
+            );
+            assert_eq!(
+                router.handle(request("GET", "/users/7/posts")).code.value(),
+                404
+            );
+        }
+
+        #[test]
+        fn captures_wildcard_rest() {
+            let mut router = Router::new();
+            router.get("/static/*rest", respond_with_params);
+            assert_eq!(
+                body(router.handle(request("GET", "/static/css/site.css"))),
+                "\"rest=css/site.css\""
+            );
+        }
+
+        #[test]
+        fn prefers_static_then_param_then_wildcard() {
+            let mut router = Router::new();
+            router
+                .get("/files/*path", respond_with("wildcard"))
+                .get("/files/:name", respond_with("param"))
+                .get("/files/readme", respond_with("static"));
+
+            assert_eq!(
+                body(router.handle(request("GET", "/files/readme"))),
+                "\"static\""
+            );
+            assert_eq!(
+                body(router.handle(request("GET", "/files/notes"))),
+                "\"param\""
+            );
+            assert_eq!(
+                body(router.handle(request("GET", "/files/a/b"))),
+                "\"wildcard\""
+            );
+        }
+
+        #[test]
+        fn backtracks_out_of_static_branch() {
+            let mut router = Router::new();
+            router
+                .get("/users/new", respond_with("new"))
+                .get("/users/:id/edit", respond_with_params);
+            assert_eq!(
+                body(router.handle(request("GET", "/users/new/edit"))),
+                "\"id=new\""
+            );
+        }
+
+        #[test]
+        fn rejects_unregistered_method_with_allow() {
+            let mut router = Router::new();
+            router
+                .get("/items/:id", respond_with("get"))
+                .delete("/items/:id", respond_with("delete"));
+
+            let response = router.handle(request("PUT", "/items/1"));
+            assert_eq!(response.code.value(), 405);
+            assert_eq!(response.header("Allow"), Some("DELETE, GET, HEAD, OPTIONS"));
+        }
+
+        #[test]
+        fn serves_head_with_get_handler() {
+            let mut router = Router::new();
+            router
+                .get("/", respond_with("get"))
+                .post("/form", respond_with("post"));
+
+            assert_eq!(body(router.handle(request("HEAD", "/"))), "\"get\"");
+            let response = router.handle(request("HEAD", "/form"));
+            assert_eq!(response.code.value(), 405);
+            assert_eq!(response.header("Allow"), Some("OPTIONS, POST"));
+        }
+
+        #[test]
+        fn answers_options_automatically() {
+            let mut router = Router::new();
+            router.post("/", respond_with("post"));
+
+            let response = router.handle(request("OPTIONS", "/"));
+            assert_eq!(response.code.value(), 204);
+            assert_eq!(
+                response.headers.iter().collect::<Vec<_>>(),
+                [("Allow", "OPTIONS, POST")]
+            );
+            assert_eq!(router.handle(request("OPTIONS", "/nope")).code.value(), 404);
+        }
     }
 }